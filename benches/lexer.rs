@@ -0,0 +1,49 @@
+//! Lexer throughput on a generated multi-megabyte script.
+//!
+//! Run with `cargo bench --bench lexer`.
+
+use std::hint::black_box;
+use std::time::Instant;
+use tik::scanner::Scanner;
+
+const SIZE: usize = 4 * 1024 * 1024;
+const RUNS: usize = 10;
+
+/// Repeats a class that uses every kind of token until the script reaches `SIZE` bytes.
+fn script() -> String {
+    let mut source = String::with_capacity(SIZE);
+    let mut i = 0;
+    while source.len() < SIZE {
+        source += &format!("\
+class Shape{i} < Base {{
+    area(width, height) {{
+        var result = width * height / 2.5 - {i};
+        if (result >= 10 and !(result == 3) or result <= -1) {{ return \"big {i}\"; }}
+        // a line comment
+        /* a block comment */
+        for (var j = 0; j < {i}; j = j + 1) print this.name;
+        return result;
+    }}
+}}
+");
+        i += 1;
+    }
+    source
+}
+
+fn main() {
+    let source = script();
+    let tokens = Scanner::new(&source).scan_tokens().0.len();
+
+    let mut runs = Vec::with_capacity(RUNS);
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        black_box(Scanner::new(black_box(&source)).scan_tokens());
+        runs.push(start.elapsed());
+    }
+    runs.sort();
+
+    let median = runs[RUNS / 2];
+    let mib = source.len() as f64 / (1024.0 * 1024.0);
+    println!("lexer: {mib:.1} MiB, {tokens} tokens, median of {RUNS} runs {median:.2?} ({:.1} MiB/s)", mib / median.as_secs_f64());
+}
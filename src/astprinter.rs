@@ -1,5 +1,6 @@
-use crate::TikError;
+use crate::error::TikError;
 use crate::expr::*;
+use crate::stmt::*;
 
 pub struct AstPrinter;
 
@@ -8,7 +9,32 @@ impl AstPrinter {
         expr.accept(self)
     }
 
-    fn parenthesize(&self, name: &String, exprs: &[&Box<Expr>]) -> Result<String, TikError> {
+    pub fn print_stmt(&self, stmt: &Stmt) -> Result<String, TikError> {
+        stmt.accept(self)
+    }
+
+    fn print_function(&self, kind: &str, stmt: &FunctionStmt) -> Result<String, TikError> {
+        let params: Vec<&str> = stmt.params.iter().map(|param| param.lexeme.as_str()).collect();
+        let mut builder = format!("({kind} {} ({})", stmt.name.lexeme, params.join(" "));
+
+        for body in stmt.body.iter() {
+            builder = format!("{builder} {}", body.accept(self)?);
+        }
+
+        Ok(format!("{builder})"))
+    }
+
+    fn print_block(&self, name: &str, statements: &[Stmt]) -> Result<String, TikError> {
+        let mut builder = format!("({name}");
+
+        for statement in statements {
+            builder = format!("{builder} {}", statement.accept(self)?);
+        }
+
+        Ok(format!("{builder})"))
+    }
+
+    fn parenthesize(&self, name: &str, exprs: &[&Expr]) -> Result<String, TikError> {
         let mut builder = format!("({name}");
 
         for expr in exprs {
@@ -21,12 +47,26 @@ impl AstPrinter {
 }
 
 impl ExprVisitor<String> for AstPrinter {
+    fn visit_assign_expr(&self, expr: &AssignExpr) -> Result<String, TikError> {
+        self.parenthesize(&format!("= {}", expr.name.lexeme), &[&expr.value])
+    }
+
     fn visit_binary_expr(&self, expr: &BinaryExpr) -> Result<String, TikError> {
         self.parenthesize(&expr.operator.lexeme, &[&expr.left, &expr.right])
     }
 
+    fn visit_call_expr(&self, expr: &CallExpr) -> Result<String, TikError> {
+        let mut exprs = vec![expr.callee.as_ref()];
+        exprs.extend(expr.arguments.iter());
+        self.parenthesize("call", &exprs)
+    }
+
+    fn visit_get_expr(&self, expr: &GetExpr) -> Result<String, TikError> {
+        self.parenthesize(&format!(". {}", expr.name.lexeme), &[&expr.object])
+    }
+
     fn visit_grouping_expr(&self, expr: &GroupingExpr) -> Result<String, TikError> {
-        self.parenthesize(&"group".to_string(), &[&expr.expression])
+        self.parenthesize("group", &[&expr.expression])
     }
     
     fn visit_literal_expr(&self, expr: &LiteralExpr) -> Result<String, TikError> {
@@ -37,7 +77,108 @@ impl ExprVisitor<String> for AstPrinter {
         }
     }
 
+    fn visit_logical_expr(&self, expr: &LogicalExpr) -> Result<String, TikError> {
+        self.parenthesize(&expr.operator.lexeme, &[&expr.left, &expr.right])
+    }
+
+    fn visit_set_expr(&self, expr: &SetExpr) -> Result<String, TikError> {
+        self.parenthesize(&format!("= {}", expr.name.lexeme), &[&expr.object, &expr.value])
+    }
+
+    fn visit_super_expr(&self, expr: &SuperExpr) -> Result<String, TikError> {
+        Ok(format!("(super {})", expr.method.lexeme))
+    }
+
+    fn visit_this_expr(&self, _expr: &ThisExpr) -> Result<String, TikError> {
+        Ok("this".to_string())
+    }
+
     fn visit_unary_expr(&self, expr: &UnaryExpr) -> Result<String, TikError> {
         self.parenthesize(&expr.operator.lexeme, &[&expr.right])
     }
+
+    fn visit_variable_expr(&self, expr: &VariableExpr) -> Result<String, TikError> {
+        Ok(expr.name.lexeme.to_string())
+    }
+}
+
+impl StmtVisitor<String> for AstPrinter {
+    fn visit_block_stmt(&self, stmt: &BlockStmt) -> Result<String, TikError> {
+        self.print_block("block", &stmt.statements)
+    }
+
+    fn visit_class_stmt(&self, stmt: &ClassStmt) -> Result<String, TikError> {
+        let mut builder = format!("(class {}", stmt.name.lexeme);
+
+        if let Some(superclass) = &stmt.superclass {
+            builder = format!("{builder} < {}", superclass.accept(self)?);
+        }
+
+        for method in &stmt.methods {
+            builder = format!("{builder} {}", self.print_function("method", method)?);
+        }
+
+        Ok(format!("{builder})"))
+    }
+
+    fn visit_expression_stmt(&self, stmt: &ExpressionStmt) -> Result<String, TikError> {
+        self.parenthesize(";", &[&stmt.expression])
+    }
+
+    fn visit_function_stmt(&self, stmt: &FunctionStmt) -> Result<String, TikError> {
+        self.print_function("fun", stmt)
+    }
+
+    fn visit_if_stmt(&self, stmt: &IfStmt) -> Result<String, TikError> {
+        let condition = stmt.condition.accept(self)?;
+        let then_branch = stmt.then_branch.accept(self)?;
+
+        if let Some(else_branch) = &stmt.else_branch {
+            Ok(format!("(if {condition} {then_branch} {})", else_branch.accept(self)?))
+        } else {
+            Ok(format!("(if {condition} {then_branch})"))
+        }
+    }
+
+    fn visit_print_stmt(&self, stmt: &PrintStmt) -> Result<String, TikError> {
+        self.parenthesize("print", &[&stmt.expression])
+    }
+
+    fn visit_return_stmt(&self, stmt: &ReturnStmt) -> Result<String, TikError> {
+        if let Some(value) = &stmt.value {
+            self.parenthesize("return", &[value])
+        } else {
+            Ok("(return)".to_string())
+        }
+    }
+
+    fn visit_throw_stmt(&self, stmt: &ThrowStmt) -> Result<String, TikError> {
+        self.parenthesize("throw", &[&stmt.value])
+    }
+
+    fn visit_try_stmt(&self, stmt: &TryStmt) -> Result<String, TikError> {
+        let mut builder = format!("(try {}", self.print_block("block", &stmt.body)?);
+
+        if let Some(catch_clause) = &stmt.catch_clause {
+            builder = format!("{builder} {}", self.print_block(&format!("catch {}", catch_clause.name.lexeme), &catch_clause.body)?);
+        }
+
+        if let Some(finally_body) = &stmt.finally_body {
+            builder = format!("{builder} {}", self.print_block("finally", finally_body)?);
+        }
+
+        Ok(format!("{builder})"))
+    }
+
+    fn visit_var_stmt(&self, stmt: &VarStmt) -> Result<String, TikError> {
+        if let Some(initializer) = &stmt.initializer {
+            self.parenthesize(&format!("var {} =", stmt.name.lexeme), &[initializer])
+        } else {
+            Ok(format!("(var {})", stmt.name.lexeme))
+        }
+    }
+
+    fn visit_while_stmt(&self, stmt: &WhileStmt) -> Result<String, TikError> {
+        Ok(format!("(while {} {})", stmt.condition.accept(self)?, stmt.body.accept(self)?))
+    }
 }
@@ -0,0 +1,354 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::*;
+
+/// An integer of any size, for the results that don't fit in an `i64`. The
+/// magnitude is base 2^32 digits, least significant first, with no leading
+/// zeros, so zero is an empty magnitude and never negative.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+const BASE: u64 = 1 << 32;
+
+// The largest power of ten that fits in a digit, for converting to and from decimal
+const DECIMAL_BASE: u32 = 1_000_000_000;
+const DECIMAL_DIGITS: usize = 9;
+
+impl BigInt {
+    fn new(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        BigInt { negative: negative && !magnitude.is_empty(), magnitude }
+    }
+
+    /// Parses a run of ASCII digits, as the scanner finds them in a literal.
+    pub fn from_decimal(digits: &str) -> BigInt {
+        let mut magnitude = Vec::new();
+        let leading = digits.len() % DECIMAL_DIGITS;
+        let chunks = std::iter::once(&digits[..leading])
+            .chain(digits.as_bytes()[leading..].chunks(DECIMAL_DIGITS).map(|chunk| std::str::from_utf8(chunk).unwrap()));
+
+        for chunk in chunks.filter(|chunk| !chunk.is_empty()) {
+            let scale = 10u32.pow(chunk.len() as u32);
+            mul_add_digit(&mut magnitude, scale, chunk.parse().unwrap());
+        }
+        BigInt::new(false, magnitude)
+    }
+
+    /// How many bits the magnitude takes up.
+    pub fn bits(&self) -> u64 {
+        match self.magnitude.last() {
+            Some(digit) => 32 * self.magnitude.len() as u64 - u64::from(digit.leading_zeros()),
+            None => 0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let value = self.magnitude.iter().rev().fold(0i128, |value, &digit| value << 32 | digit as i128);
+        i64::try_from(if self.negative { -value } else { value }).ok()
+    }
+
+    /// The nearest float, or an infinity when it's beyond `f64`'s range.
+    pub fn to_f64(&self) -> f64 {
+        let value = self.magnitude.iter().rev().fold(0.0, |value, &digit| value * BASE as f64 + digit as f64);
+        if self.negative { -value } else { value }
+    }
+
+    /// Quotient and remainder with the quotient rounded down, so the remainder
+    /// takes the sign of the divisor. Panics when dividing by zero.
+    pub fn div_mod_floor(&self, other: &BigInt) -> (BigInt, BigInt) {
+        let (quotient, remainder) = div_rem_magnitude(&self.magnitude, &other.magnitude);
+        let quotient = BigInt::new(self.negative != other.negative, quotient);
+        let remainder = BigInt::new(self.negative, remainder);
+
+        if !remainder.is_zero() && remainder.negative != other.negative {
+            (&quotient - &BigInt::from(1), &remainder + other)
+        } else {
+            (quotient, remainder)
+        }
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> BigInt {
+        let mut result = BigInt::from(1);
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    /// `self / other` as a float. Dividing exactly first keeps the result right
+    /// when both are too large to be floats themselves. Panics when dividing by
+    /// zero.
+    pub fn div_f64(&self, other: &BigInt) -> f64 {
+        let (quotient, remainder) = div_rem_magnitude(&self.magnitude, &other.magnitude);
+
+        // Only the leading digits of the divisor matter to a float, so drop the
+        // same number of trailing digits from both to keep them in range
+        let skip = other.magnitude.len().saturating_sub(3);
+        let fraction = BigInt::new(false, remainder[skip.min(remainder.len())..].to_vec()).to_f64()
+            / BigInt::new(false, other.magnitude[skip..].to_vec()).to_f64();
+
+        let value = BigInt::new(false, quotient).to_f64() + fraction;
+        if self.negative != other.negative { -value } else { value }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> BigInt {
+        let magnitude = value.unsigned_abs();
+        BigInt::new(value < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.magnitude.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+    fn add(self, rhs: &BigInt) -> BigInt {
+        if self.negative == rhs.negative {
+            return BigInt::new(self.negative, add_magnitude(&self.magnitude, &rhs.magnitude));
+        }
+        match compare_magnitude(&self.magnitude, &rhs.magnitude) {
+            Ordering::Less => BigInt::new(rhs.negative, sub_magnitude(&rhs.magnitude, &self.magnitude)),
+            _ => BigInt::new(self.negative, sub_magnitude(&self.magnitude, &rhs.magnitude)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+    fn sub(self, rhs: &BigInt) -> BigInt {
+        self + &-rhs
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+    fn mul(self, rhs: &BigInt) -> BigInt {
+        let mut product = vec![0u32; self.magnitude.len() + rhs.magnitude.len()];
+        for (i, &left) in self.magnitude.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &right) in rhs.magnitude.iter().enumerate() {
+                let digit = product[i + j] as u64 + left as u64 * right as u64 + carry;
+                product[i + j] = digit as u32;
+                carry = digit >> 32;
+            }
+            product[i + rhs.magnitude.len()] = carry as u32;
+        }
+        BigInt::new(self.negative != rhs.negative, product)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => compare_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // Peel off nine decimal digits at a time, least significant first
+        let mut chunks = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            chunks.push(div_rem_digit(&mut magnitude, DECIMAL_BASE));
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for chunk in chunks {
+            write!(f, "{chunk:0width$}", width = DECIMAL_DIGITS)?;
+        }
+        Ok(())
+    }
+}
+
+fn compare_magnitude(left: &[u32], right: &[u32]) -> Ordering {
+    left.len().cmp(&right.len()).then_with(|| left.iter().rev().cmp(right.iter().rev()))
+}
+
+fn add_magnitude(left: &[u32], right: &[u32]) -> Vec<u32> {
+    let (long, short) = if left.len() >= right.len() { (left, right) } else { (right, left) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &digit) in long.iter().enumerate() {
+        let digit = digit as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        sum.push(digit as u32);
+        carry = digit >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+/// `left - right`, where `left` is at least as large as `right`.
+fn sub_magnitude(left: &[u32], right: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(left.len());
+    let mut borrow = 0i64;
+    for (i, &digit) in left.iter().enumerate() {
+        let digit = digit as i64 - *right.get(i).unwrap_or(&0) as i64 - borrow;
+        difference.push(digit as u32);
+        borrow = (digit < 0) as i64;
+    }
+    difference
+}
+
+/// `magnitude * scale + addend`, in place.
+fn mul_add_digit(magnitude: &mut Vec<u32>, scale: u32, addend: u32) {
+    let mut carry = addend as u64;
+    for digit in magnitude.iter_mut() {
+        let product = *digit as u64 * scale as u64 + carry;
+        *digit = product as u32;
+        carry = product >> 32;
+    }
+    if carry > 0 {
+        magnitude.push(carry as u32);
+    }
+}
+
+/// Divides in place by a single digit and returns the remainder.
+fn div_rem_digit(magnitude: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+    for digit in magnitude.iter_mut().rev() {
+        let dividend = remainder << 32 | *digit as u64;
+        *digit = (dividend / divisor as u64) as u32;
+        remainder = dividend % divisor as u64;
+    }
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+    remainder as u32
+}
+
+/// Truncating division of magnitudes, by Knuth's algorithm D (TAOCP 4.3.1).
+fn div_rem_magnitude(dividend: &[u32], divisor: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    assert!(!divisor.is_empty(), "division by zero");
+
+    if compare_magnitude(dividend, divisor) == Ordering::Less {
+        return (Vec::new(), dividend.to_vec());
+    }
+    if let [divisor] = divisor {
+        let mut quotient = dividend.to_vec();
+        let remainder = div_rem_digit(&mut quotient, *divisor);
+        return (quotient, vec![remainder]);
+    }
+
+    // Shift both so the divisor's top digit has its high bit set, which keeps
+    // each estimated quotient digit at most two too large
+    let shift = divisor.last().unwrap().leading_zeros();
+    let divisor = shift_left(divisor, shift);
+    let mut remainder = shift_left(dividend, shift);
+    remainder.push(0);
+
+    let n = divisor.len();
+    let mut quotient = vec![0u32; remainder.len() - n];
+    for j in (0..quotient.len()).rev() {
+        let top = (remainder[j + n] as u64) << 32 | remainder[j + n - 1] as u64;
+        let mut estimate = top / divisor[n - 1] as u64;
+        let mut rest = top % divisor[n - 1] as u64;
+        while estimate >= BASE || estimate * divisor[n - 2] as u64 > (rest << 32 | remainder[j + n - 2] as u64) {
+            estimate -= 1;
+            rest += divisor[n - 1] as u64;
+            if rest >= BASE {
+                break;
+            }
+        }
+
+        // Subtract estimate * divisor from the current window of the remainder
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = estimate * divisor[i] as u64 + carry;
+            carry = product >> 32;
+            let digit = remainder[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+            remainder[i + j] = digit as u32;
+            borrow = (digit < 0) as i64;
+        }
+        let digit = remainder[j + n] as i64 - borrow - carry as i64;
+        remainder[j + n] = digit as u32;
+
+        // The estimate was one too large: add a divisor back
+        if digit < 0 {
+            estimate -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = remainder[i + j] as u64 + divisor[i] as u64 + carry;
+                remainder[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            remainder[j + n] = remainder[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = estimate as u32;
+    }
+
+    remainder.truncate(n);
+    (quotient, shift_right(&remainder, shift))
+}
+
+fn shift_left(magnitude: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return magnitude.to_vec();
+    }
+    let mut shifted = Vec::with_capacity(magnitude.len() + 1);
+    let mut carry = 0;
+    for &digit in magnitude {
+        shifted.push(digit << shift | carry);
+        carry = digit >> (32 - shift);
+    }
+    if carry > 0 {
+        shifted.push(carry);
+    }
+    shifted
+}
+
+fn shift_right(magnitude: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return magnitude.to_vec();
+    }
+    let mut shifted = vec![0; magnitude.len()];
+    for i in 0..magnitude.len() {
+        let high = magnitude.get(i + 1).map_or(0, |digit| digit << (32 - shift));
+        shifted[i] = magnitude[i] >> shift | high;
+    }
+    shifted
+}
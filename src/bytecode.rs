@@ -0,0 +1,456 @@
+use crate::chunk::*;
+use crate::parser::MAX_NESTING;
+use crate::error::*;
+use crate::token::*;
+use crate::symbol::*;
+use crate::bigint::*;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+// A `.tikc` file, with every integer little-endian:
+//
+//   "TIKC", format version u16, checksum u32, path of the source script, script function
+//
+// The checksum is FNV-1a over everything after it, so a corrupted file is refused
+// instead of handing the VM code that only looks well formed.
+//
+// A function is its name, arity u32, upvalue count u32, then its chunk: the code,
+// the line table as runs of (length u32, line u32, span as 4 x u32), the constants
+// (tag u8 then an f64, an i64, a string, or a big integer as its decimal string)
+// and the nested functions. Strings and lists are prefixed with their u32 length.
+
+const MAGIC: &[u8; 4] = b"TIKC";
+
+/// Bumped whenever the opcodes or the layout change. Files written by any other
+/// version are rejected rather than run.
+pub const FORMAT_VERSION: u16 = 4;
+
+const NUM: u8 = 0;
+const STR: u8 = 1;
+const INT: u8 = 2;
+const BIG: u8 = 3;
+
+/// A script loaded from a `.tikc` file.
+pub struct Compiled {
+    // The script it was compiled from, which errors point back into
+    pub source: String,
+    pub function: Function,
+}
+
+pub fn write(source: &str, function: &Function) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_str(&mut body, source);
+    write_function(&mut body, function);
+
+    let mut out = MAGIC.to_vec();
+    out.extend(FORMAT_VERSION.to_le_bytes());
+    out.extend(checksum(&body).to_le_bytes());
+    out.extend(body);
+    out
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend((value as u32).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    write_u32(out, value.len());
+    out.extend(value.as_bytes());
+}
+
+fn write_function(out: &mut Vec<u8>, function: &Function) {
+    write_str(out, &function.name);
+    write_u32(out, function.arity);
+    write_u32(out, function.upvalue_count);
+
+    let chunk = &function.chunk;
+    write_u32(out, chunk.code.len());
+    out.extend(&chunk.code);
+
+    write_u32(out, chunk.runs.len());
+    for (index, run) in chunk.runs.iter().enumerate() {
+        let end = chunk.runs.get(index + 1).map_or(chunk.code.len(), |next| next.start);
+        let span = run.span;
+        for value in [end - run.start, run.line, span.start, span.end, span.column, span.end_column] {
+            write_u32(out, value);
+        }
+    }
+
+    write_u32(out, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant {
+            Object::Num(number) => {
+                out.push(NUM);
+                out.extend(number.to_le_bytes());
+            },
+            Object::Int(number) => {
+                out.push(INT);
+                out.extend(number.to_le_bytes());
+            },
+            Object::Big(number) => {
+                out.push(BIG);
+                write_str(out, &number.to_string());
+            },
+            constant => {
+                out.push(STR);
+                match constant {
+                    Object::Str(text) => write_str(out, text),
+                    constant => write_str(out, &constant.to_string()),
+                }
+            },
+        }
+    }
+
+    write_u32(out, chunk.functions.len());
+    for nested in &chunk.functions {
+        write_function(out, nested);
+    }
+}
+
+pub fn read(bytes: &[u8], path: &str) -> Result<Compiled, TikError> {
+    let mut reader = Reader { bytes, position: 0, path, depth: 0 };
+
+    if bytes.get(..MAGIC.len()) != Some(MAGIC) {
+        return Err(TikError::bytecode_error(BytecodeError::NotBytecode, format!("'{path}' is not a tik bytecode file")));
+    }
+    reader.position = MAGIC.len();
+
+    let version = u16::from_le_bytes([reader.byte()?, reader.byte()?]);
+    if version != FORMAT_VERSION {
+        return Err(TikError::bytecode_error(BytecodeError::UnsupportedVersion,
+            format!("'{path}' was compiled for bytecode version {version}, but this tik runs version {FORMAT_VERSION}"))
+            .with_help("recompile it with `tik compile` from the original script".to_string()));
+    }
+
+    let expected = reader.u32()? as u32;
+    let body = reader.position;
+
+    let source = reader.string()?;
+    let function = reader.function()?;
+    if reader.position != bytes.len() {
+        return Err(reader.malformed("trailing bytes after the script"));
+    }
+    if checksum(&bytes[body..]) != expected {
+        return Err(reader.malformed("the checksum doesn't match its contents"));
+    }
+    if function.upvalue_count != 0 {
+        return Err(reader.malformed("the script captures variables"));
+    }
+    if function.arity != 0 {
+        return Err(reader.malformed("the script takes parameters"));
+    }
+    Ok(Compiled { source, function })
+}
+
+/// What `verify_stack` knows at an instruction: the height of the function's
+/// stack window, the handlers it pushed, how many caught errors are waiting,
+/// and which slots closures may have captured.
+#[derive(Clone)]
+struct StackState {
+    height: usize,
+    handlers: Vec<LiveHandler>,
+    pending: usize,
+    captured: BTreeSet<usize>,
+}
+
+impl StackState {
+    /// The state an error inside `handler` resumes in at its target.
+    fn caught(&self, handler: usize) -> StackState {
+        let LiveHandler { height, pending, .. } = self.handlers[handler];
+        StackState {
+            height,
+            handlers: self.handlers[..handler].to_vec(),
+            pending: pending + 1,
+            captured: self.captured.range(..height).copied().collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct LiveHandler {
+    target: usize,
+    height: usize,
+    pending: usize,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    path: &'a str,
+    // Functions being read around the current one
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], TikError> {
+        let bytes = self.position.checked_add(length)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| TikError::bytecode_error(BytecodeError::Truncated, format!("'{}' is truncated", self.path)))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, TikError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, TikError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, TikError> {
+        let length = self.u32()?;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.malformed("a string is not valid UTF-8"))
+    }
+
+    fn malformed(&self, detail: &str) -> TikError {
+        TikError::bytecode_error(BytecodeError::Malformed, format!("'{}' is malformed: {detail}", self.path))
+    }
+
+    /// Reads a function and checks its code can run without reading outside
+    /// the chunk or misusing the stack.
+    fn function(&mut self) -> Result<Function, TikError> {
+        let name = self.string()?;
+        let arity = self.u32()?;
+        let upvalue_count = self.u32()?;
+
+        let length = self.u32()?;
+        let code = self.take(length)?.to_vec();
+
+        let mut runs = Vec::new();
+        let mut covered = 0;
+        for _ in 0..self.u32()? {
+            let (length, line) = (self.u32()?, self.u32()?);
+            let span = Span { start: self.u32()?, end: self.u32()?, column: self.u32()?, end_column: self.u32()? };
+            if covered + length > code.len() {
+                return Err(self.malformed("the line table is longer than the code"));
+            }
+            if length > 0 {
+                runs.push(Run { start: covered, line, span });
+                covered += length;
+            }
+        }
+        if covered != code.len() {
+            return Err(self.malformed("the line table doesn't cover the code"));
+        }
+
+        let mut constants = Vec::new();
+        for _ in 0..self.u32()? {
+            constants.push(match self.byte()? {
+                NUM => {
+                    let bytes = self.take(8)?;
+                    Object::Num(f64::from_le_bytes(bytes.try_into().unwrap()))
+                },
+                STR => Object::Str(Symbol::from(self.string()?)),
+                INT => {
+                    let bytes = self.take(8)?;
+                    Object::Int(i64::from_le_bytes(bytes.try_into().unwrap()))
+                },
+                BIG => {
+                    let text = self.string()?;
+                    let digits = text.strip_prefix('-').unwrap_or(&text);
+                    if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+                        return Err(self.malformed("invalid big integer constant"));
+                    }
+                    let number = BigInt::from_decimal(digits);
+                    Object::integer(if digits.len() < text.len() { -&number } else { number })
+                },
+                _ => return Err(self.malformed("unknown constant tag")),
+            });
+        }
+
+        // No script the parser accepts nests functions deeper than this
+        let mut functions = Vec::new();
+        for _ in 0..self.u32()? {
+            if self.depth == MAX_NESTING {
+                return Err(self.malformed("functions are nested too deeply"));
+            }
+            self.depth += 1;
+            functions.push(Rc::new(self.function()?));
+            self.depth -= 1;
+        }
+
+        let function = Function { name, arity, upvalue_count, chunk: Chunk { code, runs, constants, functions } };
+        self.verify(&function)?;
+        Ok(function)
+    }
+
+    fn verify(&self, function: &Function) -> Result<(), TikError> {
+        let chunk = &function.chunk;
+        let name = |index: u16| matches!(chunk.constants.get(index as usize), Some(Object::Str(_)));
+
+        // The length of the instruction starting at each offset, zero inside one
+        let mut lengths = vec![0; chunk.code.len()];
+        let mut offset = 0;
+        let mut last = None;
+        while offset < chunk.code.len() {
+            let op = OpCode::from_byte(chunk.code[offset])
+                .ok_or_else(|| self.malformed(&format!("unknown opcode at offset {offset}")))?;
+
+            let width = match op.operands() {
+                Operands::None => 1,
+                Operands::Byte => 2,
+                Operands::Constant | Operands::Jump | Operands::Loop => 3,
+                // The function's index, then an (is_local, index) pair per upvalue it captures
+                Operands::Closure => match chunk.code.get(offset + 1..offset + 3) {
+                    Some(&[high, low]) => 3 + chunk.functions.get(u16::from_be_bytes([high, low]) as usize)
+                        .map_or(0, |nested| 2 * nested.upvalue_count),
+                    _ => 3,
+                },
+                Operands::Class => 6,
+            };
+            if offset + width > chunk.code.len() {
+                return Err(self.malformed(&format!("the operands of {op:?} at offset {offset} run past the end")));
+            }
+
+            let valid = match op.operands() {
+                Operands::None => true,
+                Operands::Byte => match op {
+                    OpCode::GetUpvalue | OpCode::SetUpvalue => (chunk.code[offset + 1] as usize) < function.upvalue_count,
+                    _ => true,
+                },
+                Operands::Constant => match op {
+                    OpCode::Constant => (chunk.read_u16(offset + 1) as usize) < chunk.constants.len(),
+                    _ => name(chunk.read_u16(offset + 1)),
+                },
+                Operands::Jump => offset + 3 + (chunk.read_u16(offset + 1) as usize) < chunk.code.len(),
+                Operands::Loop => chunk.read_u16(offset + 1) as usize <= offset + 3,
+                Operands::Closure => (chunk.read_u16(offset + 1) as usize) < chunk.functions.len()
+                    && chunk.code[offset + 3..offset + width].chunks(2)
+                        .all(|pair| pair[0] == 1 || (pair[1] as usize) < function.upvalue_count),
+                Operands::Class => name(chunk.read_u16(offset + 1)),
+            };
+            if !valid {
+                return Err(self.malformed(&format!("bad operand for {op:?} at offset {offset}")));
+            }
+
+            last = Some(op);
+            lengths[offset] = width;
+            offset += width;
+        }
+
+        if last != Some(OpCode::Return) {
+            return Err(self.malformed(&format!("function '{}' doesn't end in a return", function.name)));
+        }
+        self.verify_stack(function, &lengths)
+    }
+
+    /// Follows every path through the code, so the VM can trust it never pops
+    /// an empty stack or below a handler, reads a local above the top, drops a
+    /// captured slot without closing it, or pops a handler or a caught error that
+    /// isn't there. Wherever paths meet, their heights and handlers must agree.
+    fn verify_stack(&self, function: &Function, lengths: &[usize]) -> Result<(), TikError> {
+        let chunk = &function.chunk;
+        let mut seen: Vec<Option<StackState>> = vec![None; chunk.code.len()];
+        let start = StackState { height: 1 + function.arity, handlers: Vec::new(), pending: 0, captured: BTreeSet::new() };
+        let mut paths = vec![(0, start)];
+
+        while let Some((offset, mut state)) = paths.pop() {
+            if lengths.get(offset).is_none_or(|&length| length == 0) {
+                return Err(self.malformed(&format!("a jump lands inside an instruction at offset {offset}")));
+            }
+            match &seen[offset] {
+                Some(known) if known.height != state.height || known.handlers != state.handlers || known.pending != state.pending => {
+                    return Err(self.malformed(&format!("the stack doesn't line up at offset {offset}")));
+                },
+                // Another path may have captured more, which still has to be followed
+                Some(known) if known.captured.is_superset(&state.captured) => continue,
+                Some(known) => state.captured.extend(&known.captured),
+                None => {},
+            }
+            seen[offset] = Some(state.clone());
+
+            // Any instruction can fail, landing at the innermost handler
+            if let Some(handler) = state.handlers.len().checked_sub(1) {
+                paths.push((state.handlers[handler].target, state.caught(handler)));
+            }
+
+            let op = OpCode::from_byte(chunk.code[offset]).unwrap();
+            let byte = chunk.code.get(offset + 1).copied().unwrap_or(0) as usize;
+            let next = offset + lengths[offset];
+            let jump = || chunk.read_u16(offset + 1) as usize;
+
+            // How many values the instruction reads, then how many it pops and pushes
+            let (needs, pops, pushes) = match op {
+                OpCode::Constant | OpCode::Nil | OpCode::True | OpCode::False |
+                OpCode::GetGlobal | OpCode::GetUpvalue | OpCode::Closure | OpCode::Catch => (0, 0, 1),
+                OpCode::GetLocal => (byte + 1, 0, 1),
+                OpCode::SetLocal => (byte + 1, 0, 0),
+                OpCode::SetGlobal | OpCode::SetUpvalue | OpCode::Inherit | OpCode::JumpIfFalse => (1, 0, 0),
+                OpCode::GetProperty | OpCode::Not | OpCode::Negate => (1, 1, 1),
+                OpCode::Pop | OpCode::DefineGlobal | OpCode::Print | OpCode::CloseUpvalue |
+                OpCode::Return | OpCode::Throw => (1, 1, 0),
+                OpCode::SetProperty | OpCode::GetSuper | OpCode::Equal | OpCode::NotEqual |
+                OpCode::Greater | OpCode::GreaterEqual | OpCode::Less | OpCode::LessEqual |
+                OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide |
+                OpCode::FloorDivide | OpCode::Modulo | OpCode::Power => (2, 2, 1),
+                OpCode::Call => (byte + 1, byte + 1, 1),
+                // The value under the methods is read even without a superclass
+                OpCode::Class => {
+                    let count = chunk.read_u16(offset + 3) as usize;
+                    (count + 1, count, 1)
+                },
+                OpCode::Jump | OpCode::Loop | OpCode::PushHandler | OpCode::PopHandler | OpCode::Rethrow => (0, 0, 0),
+            };
+            if state.height < needs {
+                return Err(self.malformed(&format!("{op:?} at offset {offset} reads past the top of the stack")));
+            }
+            // Slot 0 holds the callee, and an error after the pops mustn't leave
+            // the stack below where the handler resumes
+            let floor = state.handlers.last().map_or(1, |handler| handler.height);
+            if state.height - pops < floor {
+                return Err(self.malformed(&format!("{op:?} at offset {offset} pops below the callee or its handler")));
+            }
+            if op != OpCode::CloseUpvalue && state.captured.range(state.height - pops..).next().is_some() {
+                return Err(self.malformed(&format!("{op:?} at offset {offset} drops a captured variable")));
+            }
+
+            match op {
+                OpCode::Closure => {
+                    for pair in chunk.code[offset + 3..next].chunks(2).filter(|pair| pair[0] == 1) {
+                        if pair[1] as usize >= state.height {
+                            return Err(self.malformed(&format!("Closure at offset {offset} captures a local above the top of the stack")));
+                        }
+                        state.captured.insert(pair[1] as usize);
+                    }
+                },
+                OpCode::CloseUpvalue => {
+                    state.captured.remove(&(state.height - 1));
+                },
+                OpCode::PushHandler => {
+                    state.handlers.push(LiveHandler { target: next + jump(), height: state.height, pending: state.pending });
+                },
+                OpCode::PopHandler if state.handlers.pop().is_none() => {
+                    return Err(self.malformed(&format!("PopHandler at offset {offset} has no handler to pop")));
+                },
+                OpCode::Catch | OpCode::Rethrow if state.pending == 0 => {
+                    return Err(self.malformed(&format!("{op:?} at offset {offset} has no caught error")));
+                },
+                OpCode::Catch => state.pending -= 1,
+                OpCode::Return if !state.handlers.is_empty() => {
+                    return Err(self.malformed(&format!("Return at offset {offset} leaves a handler behind")));
+                },
+                _ => {},
+            }
+            state.height = state.height - pops + pushes;
+
+            match op {
+                OpCode::Return | OpCode::Throw | OpCode::Rethrow => {},
+                OpCode::Jump => paths.push((next + jump(), state)),
+                OpCode::Loop => paths.push((next - jump(), state)),
+                OpCode::JumpIfFalse => {
+                    paths.push((next + jump(), state.clone()));
+                    paths.push((next, state));
+                },
+                _ => paths.push((next, state)),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use crate::environment::*;
+use crate::error::*;
+use crate::gc::{self, Gc, Trace, Tracer};
+use crate::interpreter::*;
+use crate::stmt::*;
+use crate::token::*;
+use crate::symbol::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub trait TikCallable {
+    fn name(&self) -> String;
+    fn arity(&self) -> usize;
+    fn call(&self, interpreter: &Interpreter, arguments: Vec<Object>) -> Result<Object, TikError>;
+}
+
+pub struct TikFunction {
+    name: Token,
+    params: Rc<Vec<Token>>,
+    body: Rc<Vec<Stmt>>,
+    closure: Gc<RefCell<Environment>>,
+    is_initializer: bool,
+}
+
+impl TikFunction {
+    pub fn new(declaration: &FunctionStmt, closure: Gc<RefCell<Environment>>, is_initializer: bool) -> TikFunction {
+        TikFunction {
+            name: declaration.name.clone(),
+            params: Rc::clone(&declaration.params),
+            body: Rc::clone(&declaration.body),
+            closure,
+            is_initializer,
+        }
+    }
+
+    /// Returns a copy of this method whose closure has `this` bound to the instance.
+    pub fn bind(&self, instance: Object) -> TikFunction {
+        let mut environment = Environment::new_enclosing(self.closure);
+        environment.define(Symbol::intern("this"), instance);
+
+        TikFunction {
+            name: self.name.clone(),
+            params: Rc::clone(&self.params),
+            body: Rc::clone(&self.body),
+            closure: gc::alloc(RefCell::new(environment)),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+impl TikCallable for TikFunction {
+    fn name(&self) -> String {
+        self.name.lexeme.to_string()
+    }
+
+    fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    fn call(&self, interpreter: &Interpreter, arguments: Vec<Object>) -> Result<Object, TikError> {
+        let mut environment = Environment::new_enclosing(self.closure);
+        for (param, argument) in self.params.iter().zip(arguments) {
+            environment.define(param.lexeme.clone(), argument);
+        }
+
+        let result = match interpreter.execute_block(&self.body, gc::alloc(RefCell::new(environment))) {
+            Ok(()) => Object::Nil,
+            Err(Unwind::Return(value)) => value,
+            Err(Unwind::Error(e)) => return Err(e),
+        };
+
+        // An initializer always hands back the instance, even on a bare `return;`
+        if self.is_initializer {
+            Ok(self.closure.borrow().get_local(&Symbol::intern("this")).unwrap_or(Object::Nil))
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+impl Trace for TikFunction {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self.closure);
+    }
+}
+
+impl PartialEq for TikFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for TikFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name.lexeme)
+    }
+}
+
+impl fmt::Display for TikFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name.lexeme)
+    }
+}
+
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: fn(&[Object]) -> Object,
+}
+
+impl NativeFunction {
+    /// The functions every script starts with, whichever backend runs it.
+    pub fn globals() -> Vec<NativeFunction> {
+        vec![
+            NativeFunction {
+                name: "clock".to_string(),
+                arity: 0,
+                function: |_| {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                    Object::Num(now.as_secs_f64())
+                },
+            },
+        ]
+    }
+}
+
+impl TikCallable for NativeFunction {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(&self, _interpreter: &Interpreter, arguments: Vec<Object>) -> Result<Object, TikError> {
+        Ok((self.function)(&arguments))
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
@@ -0,0 +1,159 @@
+use crate::token::*;
+use crate::symbol::*;
+use std::rc::Rc;
+
+/// One VM instruction. Operands follow the opcode byte in the chunk: constant,
+/// global and property names are `u16` indices into the constant pool, jumps are
+/// `u16` offsets, and local slots, upvalue indices and argument counts are one byte.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    FloorDivide,
+    Modulo,
+    Power,
+    Not,
+    Negate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Closure,
+    CloseUpvalue,
+    Return,
+    Inherit,
+    Class,
+    PushHandler,
+    PopHandler,
+    Catch,
+    Rethrow,
+    Throw,
+}
+
+const OPCODES: [OpCode; 45] = [
+    OpCode::Constant, OpCode::Nil, OpCode::True, OpCode::False, OpCode::Pop,
+    OpCode::GetLocal, OpCode::SetLocal, OpCode::GetGlobal, OpCode::DefineGlobal, OpCode::SetGlobal,
+    OpCode::GetUpvalue, OpCode::SetUpvalue, OpCode::GetProperty, OpCode::SetProperty, OpCode::GetSuper,
+    OpCode::Equal, OpCode::NotEqual, OpCode::Greater, OpCode::GreaterEqual, OpCode::Less,
+    OpCode::LessEqual, OpCode::Add, OpCode::Subtract, OpCode::Multiply, OpCode::Divide,
+    OpCode::FloorDivide, OpCode::Modulo, OpCode::Power, OpCode::Not, OpCode::Negate,
+    OpCode::Print, OpCode::Jump, OpCode::JumpIfFalse, OpCode::Loop, OpCode::Call,
+    OpCode::Closure, OpCode::CloseUpvalue, OpCode::Return, OpCode::Inherit, OpCode::Class,
+    OpCode::PushHandler, OpCode::PopHandler, OpCode::Catch, OpCode::Rethrow, OpCode::Throw,
+];
+
+/// The shape of what follows an opcode in the chunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operands {
+    None,
+    Byte,
+    Constant,
+    Jump,
+    Loop,
+    // A function index, then an `is_local` and index byte pair per upvalue
+    Closure,
+    // A name constant, a `u16` method count and a superclass flag byte
+    Class,
+}
+
+impl OpCode {
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OPCODES.get(byte as usize).copied()
+    }
+
+    pub fn operands(self) -> Operands {
+        match self {
+            OpCode::GetLocal | OpCode::SetLocal | OpCode::GetUpvalue | OpCode::SetUpvalue | OpCode::Call => Operands::Byte,
+            OpCode::Constant | OpCode::GetGlobal | OpCode::DefineGlobal | OpCode::SetGlobal |
+            OpCode::GetProperty | OpCode::SetProperty | OpCode::GetSuper => Operands::Constant,
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::PushHandler => Operands::Jump,
+            OpCode::Loop => Operands::Loop,
+            OpCode::Closure => Operands::Closure,
+            OpCode::Class => Operands::Class,
+            _ => Operands::None,
+        }
+    }
+}
+
+/// The bytes of a chunk from `start` up to the next run's, which all came from
+/// the same place in the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Run {
+    pub start: usize,
+    pub line: usize,
+    pub span: Span,
+}
+
+/// A compiled function body. `runs` record where each stretch of `code` came
+/// from, so the source location of any byte is known when it raises an error.
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub runs: Vec<Run>,
+    pub constants: Vec<Object>,
+    pub functions: Vec<Rc<Function>>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, line: usize, span: Span) {
+        if !matches!(self.runs.last(), Some(run) if run.line == line && run.span == span) {
+            self.runs.push(Run { start: self.code.len(), line, span });
+        }
+        self.code.push(byte);
+    }
+
+    /// The source line and span of the byte at `offset`.
+    pub fn location(&self, offset: usize) -> (usize, Span) {
+        let run = &self.runs[self.runs.partition_point(|run| run.start <= offset) - 1];
+        (run.line, run.span)
+    }
+
+    pub fn line(&self, offset: usize) -> usize {
+        self.location(offset).0
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    pub fn constant_name(&self, index: u16) -> &Symbol {
+        match &self.constants[index as usize] {
+            Object::Str(name) => name,
+            // The compiler only emits string names, and loading a `.tikc` checks them
+            constant => unreachable!("name constant {constant} is not a string"),
+        }
+    }
+}
+
+/// A function as the compiler leaves it: everything but the variables it captures.
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
@@ -0,0 +1,206 @@
+use crate::callable::*;
+use crate::error::*;
+use crate::gc::{self, Gc, Trace, Tracer};
+use crate::interpreter::*;
+use crate::token::*;
+use crate::symbol::*;
+use crate::vm::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A method body as one of the two backends built it.
+#[derive(Clone)]
+pub enum Method {
+    Function(Gc<TikFunction>),
+    Closure(Gc<Closure>),
+}
+
+impl Method {
+    pub fn arity(&self) -> usize {
+        match self {
+            Method::Function(function) => function.arity(),
+            Method::Closure(closure) => closure.function.arity,
+        }
+    }
+
+    /// The method with `this` bound to `instance`, ready to be called.
+    pub fn bind(&self, instance: Object) -> Object {
+        match self {
+            Method::Function(function) => Object::Function(gc::alloc(function.bind(instance))),
+            Method::Closure(closure) => Object::Closure(gc::alloc(closure.bind(instance))),
+        }
+    }
+}
+
+pub struct TikClass {
+    pub name: String,
+    superclass: Option<Gc<TikClass>>,
+    methods: HashMap<Symbol, Method>,
+}
+
+impl TikClass {
+    pub fn new(name: String, superclass: Option<Gc<TikClass>>, methods: HashMap<Symbol, Method>) -> TikClass {
+        TikClass { name, superclass, methods }
+    }
+
+    /// The class of the objects `catch` binds for runtime errors and thrown non-instances.
+    pub fn error() -> TikClass {
+        TikClass::new("Error".to_string(), None, HashMap::new())
+    }
+
+    pub fn find_method(&self, name: &Symbol) -> Option<Method> {
+        if let Some(method) = self.methods.get(name) {
+            Some(method.clone())
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_method(name)
+        } else {
+            None
+        }
+    }
+}
+
+impl TikCallable for Gc<TikClass> {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn arity(&self) -> usize {
+        self.find_method(&Symbol::intern("init")).map_or(0, |initializer| initializer.arity())
+    }
+
+    fn call(&self, interpreter: &Interpreter, arguments: Vec<Object>) -> Result<Object, TikError> {
+        let instance = Object::Instance(gc::alloc(TikInstance::new(*self)));
+
+        if let Some(Method::Function(initializer)) = self.find_method(&Symbol::intern("init")) {
+            initializer.bind(instance.clone()).call(interpreter, arguments)?;
+        }
+
+        Ok(instance)
+    }
+}
+
+impl Trace for TikClass {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(superclass) = self.superclass {
+            tracer.mark(superclass);
+        }
+        for method in self.methods.values() {
+            match method {
+                Method::Function(function) => tracer.mark(*function),
+                Method::Closure(closure) => tracer.mark(*closure),
+            }
+        }
+    }
+
+    fn extra_size(&self) -> usize {
+        self.name.capacity() + gc::map_size(&self.methods)
+    }
+}
+
+impl PartialEq for TikClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for TikClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<class {}>", self.name)
+    }
+}
+
+impl fmt::Display for TikClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub struct TikInstance {
+    class: Gc<TikClass>,
+    fields: RefCell<HashMap<Symbol, Object>>,
+}
+
+impl TikInstance {
+    pub fn new(class: Gc<TikClass>) -> TikInstance {
+        TikInstance { class, fields: RefCell::new(HashMap::new()) }
+    }
+
+    /// The catchable object for an error: thrown instances pass through as they are
+    /// so they can be rethrown, and everything else becomes an `Error` with
+    /// `message`, `line` and the thrown `value`.
+    pub fn from_error(error_class: Gc<TikClass>, e: TikError) -> Object {
+        let line = Object::Int(e.line() as i64);
+        let message = e.message().to_string();
+
+        let (message, value) = match e.into_thrown_value() {
+            Some(instance @ Object::Instance(_)) => return instance,
+            Some(value) => (value.describe(), value),
+            None => (message, Object::Nil),
+        };
+
+        let error = TikInstance::new(error_class);
+        error.set_field(Symbol::intern("message"), Object::Str(Symbol::from(message)));
+        error.set_field(Symbol::intern("line"), line);
+        error.set_field(Symbol::intern("value"), value);
+        Object::Instance(gc::alloc(error))
+    }
+
+    pub fn get(instance: Gc<TikInstance>, name: &Token) -> Result<Object, TikError> {
+        TikInstance::property(instance, &name.lexeme).ok_or_else(|| {
+            TikError::runtime_error(RuntimeError::UndefinedProperty, name.clone(), format!("Undefined property '{}'", name.lexeme))
+        })
+    }
+
+    /// A field, or failing that a method bound to the instance.
+    pub fn property(instance: Gc<TikInstance>, name: &Symbol) -> Option<Object> {
+        if let Some(value) = instance.field(name) {
+            return Some(value);
+        }
+
+        instance.class.find_method(name).map(|method| method.bind(Object::Instance(instance)))
+    }
+
+    pub fn set(&self, name: &Token, value: Object) {
+        self.fields.borrow_mut().insert(name.lexeme.clone(), value);
+    }
+
+    pub fn field(&self, name: &Symbol) -> Option<Object> {
+        self.fields.borrow().get(name).cloned()
+    }
+
+    pub fn set_field(&self, name: Symbol, value: Object) {
+        self.fields.borrow_mut().insert(name, value);
+    }
+}
+
+impl Trace for TikInstance {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self.class);
+        for value in self.fields.borrow().values() {
+            tracer.object(value);
+        }
+    }
+
+    fn extra_size(&self) -> usize {
+        gc::map_size(&self.fields.borrow())
+    }
+}
+
+impl PartialEq for TikInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for TikInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{} instance>", self.class.name)
+    }
+}
+
+impl fmt::Display for TikInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
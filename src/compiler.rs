@@ -0,0 +1,733 @@
+use crate::chunk::*;
+use crate::error::*;
+use crate::expr::*;
+use crate::stmt::*;
+use crate::token::*;
+use crate::token_type::*;
+use crate::symbol::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    is_captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+/// A handler pushed by an enclosing `try` or `catch`, which a `return` has to pop,
+/// running the `finally` body on its way out.
+#[derive(Clone)]
+struct TryContext {
+    finally_body: Option<Rc<Vec<Stmt>>>,
+}
+
+struct FunctionState {
+    name: String,
+    arity: usize,
+    kind: FunctionKind,
+    chunk: Chunk,
+    names: HashMap<Symbol, u16>,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    tries: Vec<TryContext>,
+}
+
+enum Variable {
+    Local(u8),
+    Upvalue(u8),
+    Global(u16),
+}
+
+/// Compiles a resolved AST into bytecode for the VM. Scoping follows the same
+/// rules as the resolver, but locals live in stack slots and the variables a
+/// closure captures become upvalues.
+pub struct Compiler {
+    states: RefCell<Vec<FunctionState>>,
+    // Where the next emitted byte came from, moved along as tokens are visited
+    line: Cell<usize>,
+    span: Cell<Span>,
+}
+
+impl Default for Compiler {
+    fn default() -> Compiler {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler { states: RefCell::new(Vec::new()), line: Cell::new(1), span: Cell::new(Span::default()) }
+    }
+
+    pub fn compile(&self, statements: &[Stmt]) -> Result<Function, TikError> {
+        self.states.borrow_mut().clear();
+        self.begin_function("<script>".to_string(), FunctionKind::Script);
+
+        for statement in statements {
+            statement.accept(self)?;
+        }
+
+        Ok(self.end_function().0)
+    }
+
+    fn begin_function(&self, name: String, kind: FunctionKind) {
+        // Slot zero holds the receiver in methods and the callee everywhere else
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+
+        self.states.borrow_mut().push(FunctionState {
+            name,
+            arity: 0,
+            kind,
+            chunk: Chunk::default(),
+            names: HashMap::new(),
+            locals: vec![Local { name: Symbol::intern(receiver), depth: 0, is_captured: false }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            tries: Vec::new(),
+        });
+    }
+
+    fn end_function(&self) -> (Function, Vec<Upvalue>) {
+        self.emit_return();
+
+        let state = self.states.borrow_mut().pop().unwrap();
+        let function = Function {
+            name: state.name,
+            arity: state.arity,
+            upvalue_count: state.upvalues.len(),
+            chunk: state.chunk,
+        };
+        (function, state.upvalues)
+    }
+
+    fn function(&self, declaration: &FunctionStmt, kind: FunctionKind) -> Result<(), TikError> {
+        self.begin_function(declaration.name.lexeme.to_string(), kind);
+        self.state().arity = declaration.params.len();
+
+        // The body's scope is never ended; returning drops the whole frame
+        self.begin_scope();
+
+        for param in declaration.params.iter() {
+            self.at(param);
+            self.add_local(&param.lexeme)?;
+        }
+        for statement in declaration.body.iter() {
+            statement.accept(self)?;
+        }
+
+        let (function, upvalues) = self.end_function();
+
+        self.at(&declaration.name);
+        let index = {
+            let mut state = self.state();
+            state.chunk.functions.push(Rc::new(function));
+            state.chunk.functions.len() - 1
+        };
+        let index = u16::try_from(index)
+            .map_err(|_| self.error(CompileError::TooManyConstants, "Too many functions in one chunk"))?;
+
+        self.emit_op(OpCode::Closure);
+        self.emit_u16(index);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> std::cell::RefMut<'_, FunctionState> {
+        std::cell::RefMut::map(self.states.borrow_mut(), |states| states.last_mut().unwrap())
+    }
+
+    fn at(&self, token: &Token) {
+        self.line.set(token.line);
+        self.span.set(token.span);
+    }
+
+    fn error(&self, kind: CompileError, message: &str) -> TikError {
+        TikError::compile_error(kind, self.line.get(), self.span.get(), message.to_string())
+    }
+
+    fn emit_byte(&self, byte: u8) {
+        let (line, span) = (self.line.get(), self.span.get());
+        self.state().chunk.write(byte, line, span);
+    }
+
+    fn emit_op(&self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_u16(&self, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.emit_byte(high);
+        self.emit_byte(low);
+    }
+
+    fn emit_return(&self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    fn emit_constant(&self, value: Object) -> Result<(), TikError> {
+        let index = {
+            let mut state = self.state();
+            state.chunk.constants.push(value);
+            state.chunk.constants.len() - 1
+        };
+        let index = u16::try_from(index)
+            .map_err(|_| self.error(CompileError::TooManyConstants, "Too many constants in one chunk"))?;
+
+        self.emit_op(OpCode::Constant);
+        self.emit_u16(index);
+        Ok(())
+    }
+
+    /// The constant holding `name`, shared by every use of the name in the chunk.
+    fn identifier_constant(&self, name: &Symbol) -> Result<u16, TikError> {
+        if let Some(index) = self.state().names.get(name) {
+            return Ok(*index);
+        }
+
+        let index = {
+            let mut state = self.state();
+            state.chunk.constants.push(Object::Str(name.clone()));
+            state.chunk.constants.len() - 1
+        };
+        let index = u16::try_from(index)
+            .map_err(|_| self.error(CompileError::TooManyConstants, "Too many constants in one chunk"))?;
+
+        self.state().names.insert(name.clone(), index);
+        Ok(index)
+    }
+
+    fn emit_jump(&self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_u16(u16::MAX);
+        self.state().chunk.code.len() - 2
+    }
+
+    fn patch_jump(&self, offset: usize) -> Result<(), TikError> {
+        let mut state = self.state();
+        let jump = u16::try_from(state.chunk.code.len() - offset - 2)
+            .map_err(|_| self.error(CompileError::JumpTooLarge, "Too much code to jump over"))?;
+
+        let [high, low] = jump.to_be_bytes();
+        state.chunk.code[offset] = high;
+        state.chunk.code[offset + 1] = low;
+        Ok(())
+    }
+
+    fn emit_loop(&self, start: usize) -> Result<(), TikError> {
+        self.emit_op(OpCode::Loop);
+        let offset = u16::try_from(self.state().chunk.code.len() - start + 2)
+            .map_err(|_| self.error(CompileError::JumpTooLarge, "Loop body too large"))?;
+        self.emit_u16(offset);
+        Ok(())
+    }
+
+    fn begin_scope(&self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&self) {
+        let mut state = self.state();
+        state.scope_depth -= 1;
+
+        let mut ops = Vec::new();
+        while state.locals.last().is_some_and(|local| local.depth > state.scope_depth) {
+            let local = state.locals.pop().unwrap();
+            ops.push(if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop });
+        }
+        drop(state);
+
+        for op in ops {
+            self.emit_op(op);
+        }
+    }
+
+    fn add_local(&self, name: &Symbol) -> Result<(), TikError> {
+        let mut state = self.state();
+        if state.locals.len() > u8::MAX as usize {
+            return Err(self.error(CompileError::TooManyLocals, "Too many local variables in function"));
+        }
+
+        let depth = state.scope_depth;
+        state.locals.push(Local { name: name.clone(), depth, is_captured: false });
+        Ok(())
+    }
+
+    fn is_global_scope(&self) -> bool {
+        self.state().scope_depth == 0
+    }
+
+    fn resolve(&self, name: &Symbol) -> Result<Variable, TikError> {
+        let mut states = self.states.borrow_mut();
+        let current = states.len() - 1;
+
+        if let Some(slot) = Compiler::resolve_local(&states[current], name) {
+            return Ok(Variable::Local(slot));
+        }
+        if let Some(index) = self.resolve_upvalue(&mut states, current, name)? {
+            return Ok(Variable::Upvalue(index));
+        }
+        drop(states);
+
+        Ok(Variable::Global(self.identifier_constant(name)?))
+    }
+
+    fn resolve_local(state: &FunctionState, name: &Symbol) -> Option<u8> {
+        state.locals.iter().rposition(|local| local.name == *name).map(|slot| slot as u8)
+    }
+
+    fn resolve_upvalue(&self, states: &mut [FunctionState], current: usize, name: &Symbol) -> Result<Option<u8>, TikError> {
+        if current == 0 {
+            return Ok(None);
+        }
+
+        if let Some(slot) = Compiler::resolve_local(&states[current - 1], name) {
+            states[current - 1].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(&mut states[current], Upvalue { index: slot, is_local: true }).map(Some);
+        }
+
+        if let Some(index) = self.resolve_upvalue(states, current - 1, name)? {
+            return self.add_upvalue(&mut states[current], Upvalue { index, is_local: false }).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn add_upvalue(&self, state: &mut FunctionState, upvalue: Upvalue) -> Result<u8, TikError> {
+        if let Some(index) = state.upvalues.iter().position(|existing| *existing == upvalue) {
+            return Ok(index as u8);
+        }
+        if state.upvalues.len() > u8::MAX as usize {
+            return Err(self.error(CompileError::TooManyUpvalues, "Too many closure variables in function"));
+        }
+
+        state.upvalues.push(upvalue);
+        Ok((state.upvalues.len() - 1) as u8)
+    }
+
+    fn get_variable(&self, name: &Symbol) -> Result<(), TikError> {
+        match self.resolve(name)? {
+            Variable::Local(slot) => {
+                self.emit_op(OpCode::GetLocal);
+                self.emit_byte(slot);
+            },
+            Variable::Upvalue(index) => {
+                self.emit_op(OpCode::GetUpvalue);
+                self.emit_byte(index);
+            },
+            Variable::Global(name) => {
+                self.emit_op(OpCode::GetGlobal);
+                self.emit_u16(name);
+            },
+        }
+        Ok(())
+    }
+
+    fn set_variable(&self, name: &Symbol) -> Result<(), TikError> {
+        match self.resolve(name)? {
+            Variable::Local(slot) => {
+                self.emit_op(OpCode::SetLocal);
+                self.emit_byte(slot);
+            },
+            Variable::Upvalue(index) => {
+                self.emit_op(OpCode::SetUpvalue);
+                self.emit_byte(index);
+            },
+            Variable::Global(name) => {
+                self.emit_op(OpCode::SetGlobal);
+                self.emit_u16(name);
+            },
+        }
+        Ok(())
+    }
+
+    /// Declares `name` in the current scope once its value is on top of the stack.
+    fn define_variable(&self, name: &Token) -> Result<(), TikError> {
+        self.at(name);
+        if self.is_global_scope() {
+            let global = self.identifier_constant(&name.lexeme)?;
+            self.emit_op(OpCode::DefineGlobal);
+            self.emit_u16(global);
+            Ok(())
+        } else {
+            self.add_local(&name.lexeme)
+        }
+    }
+
+    fn block(&self, statements: &[Stmt]) -> Result<(), TikError> {
+        self.begin_scope();
+        for statement in statements {
+            statement.accept(self)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Compiles a `try` body under the handler just pushed, popping it at the end.
+    fn guarded(&self, body: &[Stmt], finally_body: &Option<Rc<Vec<Stmt>>>) -> Result<(), TikError> {
+        self.state().tries.push(TryContext { finally_body: finally_body.clone() });
+        self.block(body)?;
+        self.state().tries.pop();
+        self.emit_op(OpCode::PopHandler);
+        Ok(())
+    }
+
+    /// Binds the pending error to the catch variable for the length of the body.
+    fn catch_body(&self, catch_clause: &CatchClause) -> Result<(), TikError> {
+        self.begin_scope();
+        self.at(&catch_clause.name);
+        self.emit_op(OpCode::Catch);
+        self.add_local(&catch_clause.name.lexeme)?;
+        for statement in &catch_clause.body {
+            statement.accept(self)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Pops the handlers of every `try` a `return` leaves, running their `finally`
+    /// bodies innermost first. The return value stays on the stack underneath.
+    fn unwind_tries(&self) -> Result<(), TikError> {
+        let saved = self.state().tries.clone();
+        self.add_local(&Symbol::intern(""))?;
+
+        loop {
+            let Some(context) = self.state().tries.pop() else {
+                break;
+            };
+            self.emit_op(OpCode::PopHandler);
+            if let Some(finally_body) = &context.finally_body {
+                self.block(finally_body)?;
+            }
+        }
+
+        let mut state = self.state();
+        state.locals.pop();
+        state.tries = saved;
+        Ok(())
+    }
+}
+
+impl StmtVisitor<()> for Compiler {
+    fn visit_block_stmt(&self, stmt: &BlockStmt) -> Result<(), TikError> {
+        self.block(&stmt.statements)
+    }
+
+    fn visit_class_stmt(&self, stmt: &ClassStmt) -> Result<(), TikError> {
+        // The name is bound to nil first so the methods can refer to the class
+        self.at(&stmt.name);
+        self.emit_op(OpCode::Nil);
+        self.define_variable(&stmt.name)?;
+
+        if let Some(superclass) = &stmt.superclass {
+            self.begin_scope();
+            superclass.accept(self)?;
+            self.at(&superclass.name);
+            self.emit_op(OpCode::Inherit);
+            self.add_local(&Symbol::intern("super"))?;
+        }
+
+        for method in &stmt.methods {
+            let kind = if method.name.lexeme == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind)?;
+        }
+
+        self.at(&stmt.name);
+        let name = self.identifier_constant(&stmt.name.lexeme)?;
+        let count = u16::try_from(stmt.methods.len())
+            .map_err(|_| self.error(CompileError::TooManyConstants, "Too many methods in one class"))?;
+        self.emit_op(OpCode::Class);
+        self.emit_u16(name);
+        self.emit_u16(count);
+        self.emit_byte(stmt.superclass.is_some() as u8);
+
+        self.set_variable(&stmt.name.lexeme)?;
+        self.emit_op(OpCode::Pop);
+
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&self, stmt: &ExpressionStmt) -> Result<(), TikError> {
+        stmt.expression.accept(self)?;
+        self.emit_op(OpCode::Pop);
+        Ok(())
+    }
+
+    fn visit_function_stmt(&self, stmt: &FunctionStmt) -> Result<(), TikError> {
+        // A local function takes its slot before its body compiles so it can call itself
+        if self.is_global_scope() {
+            self.function(stmt, FunctionKind::Function)?;
+            self.define_variable(&stmt.name)
+        } else {
+            self.define_variable(&stmt.name)?;
+            self.function(stmt, FunctionKind::Function)
+        }
+    }
+
+    fn visit_if_stmt(&self, stmt: &IfStmt) -> Result<(), TikError> {
+        stmt.condition.accept(self)?;
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        stmt.then_branch.accept(self)?;
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump)?;
+        self.emit_op(OpCode::Pop);
+        if let Some(else_branch) = &stmt.else_branch {
+            else_branch.accept(self)?;
+        }
+        self.patch_jump(else_jump)
+    }
+
+    fn visit_print_stmt(&self, stmt: &PrintStmt) -> Result<(), TikError> {
+        stmt.expression.accept(self)?;
+        self.emit_op(OpCode::Print);
+        Ok(())
+    }
+
+    fn visit_return_stmt(&self, stmt: &ReturnStmt) -> Result<(), TikError> {
+        self.at(&stmt.keyword);
+        if let Some(value) = &stmt.value {
+            value.accept(self)?;
+        } else if self.state().kind == FunctionKind::Initializer {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+
+        self.unwind_tries()?;
+        self.at(&stmt.keyword);
+        self.emit_op(OpCode::Return);
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&self, stmt: &ThrowStmt) -> Result<(), TikError> {
+        stmt.value.accept(self)?;
+        self.at(&stmt.keyword);
+        self.emit_op(OpCode::Throw);
+        Ok(())
+    }
+
+    fn visit_try_stmt(&self, stmt: &TryStmt) -> Result<(), TikError> {
+        let handler = self.emit_jump(OpCode::PushHandler);
+        self.guarded(&stmt.body, &stmt.finally_body)?;
+        let mut exits = vec![self.emit_jump(OpCode::Jump)];
+
+        // Control lands here with the error pending in the VM
+        self.patch_jump(handler)?;
+        match (&stmt.catch_clause, &stmt.finally_body) {
+            (Some(catch_clause), None) => self.catch_body(catch_clause)?,
+            (Some(catch_clause), Some(finally_body)) => {
+                // The catch body is guarded too, so the finally runs even if it fails
+                let rethrow = self.emit_jump(OpCode::PushHandler);
+                self.state().tries.push(TryContext { finally_body: Some(Rc::clone(finally_body)) });
+                self.catch_body(catch_clause)?;
+                self.state().tries.pop();
+                self.emit_op(OpCode::PopHandler);
+                exits.push(self.emit_jump(OpCode::Jump));
+
+                self.patch_jump(rethrow)?;
+                self.block(finally_body)?;
+                self.emit_op(OpCode::Rethrow);
+            },
+            (None, Some(finally_body)) => {
+                self.block(finally_body)?;
+                self.emit_op(OpCode::Rethrow);
+            },
+            (None, None) => {},
+        }
+
+        for exit in exits {
+            self.patch_jump(exit)?;
+        }
+        if let Some(finally_body) = &stmt.finally_body {
+            self.block(finally_body)?;
+        }
+        Ok(())
+    }
+
+    fn visit_var_stmt(&self, stmt: &VarStmt) -> Result<(), TikError> {
+        if let Some(initializer) = &stmt.initializer {
+            initializer.accept(self)?;
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.define_variable(&stmt.name)
+    }
+
+    fn visit_while_stmt(&self, stmt: &WhileStmt) -> Result<(), TikError> {
+        let start = self.state().chunk.code.len();
+        stmt.condition.accept(self)?;
+
+        let exit = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        stmt.body.accept(self)?;
+        self.emit_loop(start)?;
+
+        self.patch_jump(exit)?;
+        self.emit_op(OpCode::Pop);
+        Ok(())
+    }
+}
+
+impl ExprVisitor<()> for Compiler {
+    fn visit_assign_expr(&self, expr: &AssignExpr) -> Result<(), TikError> {
+        expr.value.accept(self)?;
+        self.at(&expr.name);
+        self.set_variable(&expr.name.lexeme)
+    }
+
+    fn visit_binary_expr(&self, expr: &BinaryExpr) -> Result<(), TikError> {
+        expr.left.accept(self)?;
+        expr.right.accept(self)?;
+
+        self.at(&expr.operator);
+        let op = match expr.operator.ttype {
+            TokenType::EqualEqual => OpCode::Equal,
+            TokenType::BangEqual => OpCode::NotEqual,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            TokenType::Less => OpCode::Less,
+            TokenType::LessEqual => OpCode::LessEqual,
+            TokenType::Plus => OpCode::Add,
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            TokenType::TildeSlash => OpCode::FloorDivide,
+            TokenType::Percent => OpCode::Modulo,
+            TokenType::StarStar => OpCode::Power,
+            _ => return Err(TikError::runtime_error(RuntimeError::UnsupportedOperator, expr.operator.clone(), "Unreachable".to_string())),
+        };
+        self.emit_op(op);
+        Ok(())
+    }
+
+    fn visit_call_expr(&self, expr: &CallExpr) -> Result<(), TikError> {
+        expr.callee.accept(self)?;
+        for argument in &expr.arguments {
+            argument.accept(self)?;
+        }
+
+        self.at(&expr.paren);
+        self.emit_op(OpCode::Call);
+        self.emit_byte(expr.arguments.len() as u8);
+        Ok(())
+    }
+
+    fn visit_get_expr(&self, expr: &GetExpr) -> Result<(), TikError> {
+        expr.object.accept(self)?;
+        self.at(&expr.name);
+        let name = self.identifier_constant(&expr.name.lexeme)?;
+        self.emit_op(OpCode::GetProperty);
+        self.emit_u16(name);
+        Ok(())
+    }
+
+    fn visit_grouping_expr(&self, expr: &GroupingExpr) -> Result<(), TikError> {
+        expr.expression.accept(self)
+    }
+
+    fn visit_literal_expr(&self, expr: &LiteralExpr) -> Result<(), TikError> {
+        match &expr.value {
+            None | Some(Object::Nil) => self.emit_op(OpCode::Nil),
+            Some(Object::True) => self.emit_op(OpCode::True),
+            Some(Object::False) => self.emit_op(OpCode::False),
+            Some(value) => self.emit_constant(value.clone())?,
+        }
+        Ok(())
+    }
+
+    fn visit_logical_expr(&self, expr: &LogicalExpr) -> Result<(), TikError> {
+        expr.left.accept(self)?;
+
+        if expr.operator.ttype == TokenType::Or {
+            let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+            let end_jump = self.emit_jump(OpCode::Jump);
+            self.patch_jump(else_jump)?;
+            self.emit_op(OpCode::Pop);
+            expr.right.accept(self)?;
+            self.patch_jump(end_jump)
+        } else {
+            let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+            self.emit_op(OpCode::Pop);
+            expr.right.accept(self)?;
+            self.patch_jump(end_jump)
+        }
+    }
+
+    fn visit_set_expr(&self, expr: &SetExpr) -> Result<(), TikError> {
+        expr.object.accept(self)?;
+        expr.value.accept(self)?;
+        self.at(&expr.name);
+        let name = self.identifier_constant(&expr.name.lexeme)?;
+        self.emit_op(OpCode::SetProperty);
+        self.emit_u16(name);
+        Ok(())
+    }
+
+    fn visit_super_expr(&self, expr: &SuperExpr) -> Result<(), TikError> {
+        self.at(&expr.keyword);
+        self.get_variable(&Symbol::intern("this"))?;
+        self.get_variable(&Symbol::intern("super"))?;
+
+        self.at(&expr.method);
+        let name = self.identifier_constant(&expr.method.lexeme)?;
+        self.emit_op(OpCode::GetSuper);
+        self.emit_u16(name);
+        Ok(())
+    }
+
+    fn visit_this_expr(&self, expr: &ThisExpr) -> Result<(), TikError> {
+        self.at(&expr.keyword);
+        self.get_variable(&Symbol::intern("this"))
+    }
+
+    fn visit_unary_expr(&self, expr: &UnaryExpr) -> Result<(), TikError> {
+        expr.right.accept(self)?;
+        self.at(&expr.operator);
+        match expr.operator.ttype {
+            TokenType::Minus => self.emit_op(OpCode::Negate),
+            _ => self.emit_op(OpCode::Not),
+        }
+        Ok(())
+    }
+
+    fn visit_variable_expr(&self, expr: &VariableExpr) -> Result<(), TikError> {
+        self.at(&expr.name);
+        self.get_variable(&expr.name.lexeme)
+    }
+}
@@ -0,0 +1,86 @@
+use crate::chunk::*;
+
+/// Lists a compiled function's instructions, followed by those of every
+/// function nested in it. Each row shows the byte offset, the source line
+/// (`|` when unchanged), the opcode and its operands with constants resolved.
+pub fn disassemble(function: &Function) -> String {
+    let mut out = String::new();
+    disassemble_function(function, &mut out);
+    out
+}
+
+fn disassemble_function(function: &Function, out: &mut String) {
+    *out += &format!("== {} ==\n", function.name);
+
+    let mut offset = 0;
+    while offset < function.chunk.code.len() {
+        offset = instruction(&function.chunk, offset, out);
+    }
+
+    for nested in &function.chunk.functions {
+        out.push('\n');
+        disassemble_function(nested, out);
+    }
+}
+
+fn instruction(chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    *out += &format!("{offset:04} ");
+    if offset > 0 && chunk.line(offset) == chunk.line(offset - 1) {
+        *out += "   | ";
+    } else {
+        *out += &format!("{:4} ", chunk.line(offset));
+    }
+
+    let byte = chunk.code[offset];
+    let Some(op) = OpCode::from_byte(byte) else {
+        *out += &format!("Unknown opcode {byte}\n");
+        return offset + 1;
+    };
+    let name = format!("{op:?}");
+
+    match op.operands() {
+        Operands::None => {
+            *out += &format!("{name}\n");
+            offset + 1
+        },
+        Operands::Byte => {
+            *out += &format!("{name:<16} {:4}\n", chunk.code[offset + 1]);
+            offset + 2
+        },
+        Operands::Constant => {
+            let index = chunk.read_u16(offset + 1);
+            *out += &format!("{name:<16} {index:4} {}\n", chunk.constants[index as usize]);
+            offset + 3
+        },
+        Operands::Jump => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            *out += &format!("{name:<16} {offset:4} -> {}\n", offset + 3 + jump);
+            offset + 3
+        },
+        Operands::Loop => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            *out += &format!("{name:<16} {offset:4} -> {}\n", offset + 3 - jump);
+            offset + 3
+        },
+        Operands::Closure => {
+            let index = chunk.read_u16(offset + 1);
+            let function = &chunk.functions[index as usize];
+            *out += &format!("{name:<16} {index:4} <fn {}>\n", function.name);
+
+            let mut offset = offset + 3;
+            for _ in 0..function.upvalue_count {
+                let kind = if chunk.code[offset] == 1 { "local" } else { "upvalue" };
+                *out += &format!("{offset:04}    |   {kind} {}\n", chunk.code[offset + 1]);
+                offset += 2;
+            }
+            offset
+        },
+        Operands::Class => {
+            let index = chunk.read_u16(offset + 1);
+            let methods = chunk.read_u16(offset + 3);
+            let superclass = if chunk.code[offset + 5] == 1 { " < super" } else { "" };
+            *out += &format!("{name:<16} {index:4} {} ({methods} methods){superclass}\n", chunk.constants[index as usize]);
+            offset + 6
+        },
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::*;
+use crate::gc::{self, Gc, Trace, Tracer};
+use crate::token::*;
+use crate::symbol::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+pub struct Environment {
+    values: HashMap<Symbol, Object>,
+    enclosing: Option<Gc<RefCell<Environment>>>,
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment::new()
+    }
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment { values: HashMap::new(), enclosing: None }
+    }
+
+    pub fn new_enclosing(enclosing: Gc<RefCell<Environment>>) -> Environment {
+        Environment { values: HashMap::new(), enclosing: Some(enclosing) }
+    }
+
+    pub fn define(&mut self, name: Symbol, value: Object) {
+        self.values.insert(name, value);
+    }
+
+    pub fn get_local(&self, name: &Symbol) -> Option<Object> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Object, TikError> {
+        if let Some(value) = self.values.get(&name.lexeme) {
+            Ok(value.clone())
+        } else if let Some(enclosing) = &self.enclosing {
+            enclosing.borrow().get(name)
+        } else {
+            Err(Environment::undefined(name))
+        }
+    }
+
+    /// Reads a variable the resolver found exactly `distance` scopes up.
+    pub fn get_at(&self, distance: usize, name: &Token) -> Result<Object, TikError> {
+        if distance == 0 {
+            self.get_local(&name.lexeme).ok_or_else(|| Environment::undefined(name))
+        } else if let Some(enclosing) = &self.enclosing {
+            enclosing.borrow().get_at(distance - 1, name)
+        } else {
+            Err(Environment::undefined(name))
+        }
+    }
+
+    pub fn assign_at(&mut self, distance: usize, name: &Token, value: Object) -> Result<(), TikError> {
+        if distance == 0 {
+            self.values.insert(name.lexeme.clone(), value);
+            Ok(())
+        } else if let Some(enclosing) = &self.enclosing {
+            enclosing.borrow_mut().assign_at(distance - 1, name, value)
+        } else {
+            Err(Environment::undefined(name))
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), TikError> {
+        if let Some(slot) = self.values.get_mut(&name.lexeme) {
+            *slot = value;
+            Ok(())
+        } else if let Some(enclosing) = &self.enclosing {
+            enclosing.borrow_mut().assign(name, value)
+        } else {
+            Err(Environment::undefined(name))
+        }
+    }
+
+    fn undefined(name: &Token) -> TikError {
+        TikError::undefined_variable(&name.lexeme, name.line, name.span)
+    }
+}
+
+impl Trace for RefCell<Environment> {
+    fn trace(&self, tracer: &mut Tracer) {
+        let environment = self.borrow();
+        for value in environment.values.values() {
+            tracer.object(value);
+        }
+        if let Some(enclosing) = environment.enclosing {
+            tracer.mark(enclosing);
+        }
+    }
+
+    fn extra_size(&self) -> usize {
+        gc::map_size(&self.borrow().values)
+    }
+}
@@ -1,45 +1,380 @@
 use crate::token::*;
 use crate::token_type::*;
+use std::fmt;
+use std::io;
+
+/// Every error the interpreter can produce, grouped by the phase that raises it.
+/// Each variant maps to a stable code of the form `E<phase><number>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Lex(LexError),
+    Parse(ParseError),
+    Resolve(ResolveError),
+    Runtime(RuntimeError),
+    Io,
+    Compile(CompileError),
+    Bytecode(BytecodeError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LexError {
+    UnexpectedCharacter = 1,
+    UnterminatedString = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseError {
+    ExpectedExpression = 1,
+    ExpectedToken = 2,
+    InvalidAssignmentTarget = 3,
+    TooManyParameters = 4,
+    TooManyArguments = 5,
+    InheritFromSelf = 6,
+    TooDeeplyNested = 7,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResolveError {
+    ReadInOwnInitializer = 1,
+    DuplicateVariable = 2,
+    TopLevelReturn = 3,
+    ReturnFromInitializer = 4,
+    ThisOutsideClass = 5,
+    SuperOutsideClass = 6,
+    SuperWithoutSuperclass = 7,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuntimeError {
+    UndefinedVariable = 1,
+    UndefinedProperty = 2,
+    NotCallable = 3,
+    ArityMismatch = 4,
+    InvalidOperands = 5,
+    NotAnInstance = 6,
+    SuperclassNotClass = 7,
+    UnsupportedOperator = 8,
+    Thrown = 9,
+    DivisionByZero = 10,
+    StackOverflow = 11,
+    IntegerTooLarge = 12,
+}
+
+/// Limits of the bytecode format, hit only by the VM backend's compiler.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompileError {
+    TooManyConstants = 1,
+    TooManyLocals = 2,
+    TooManyUpvalues = 3,
+    JumpTooLarge = 4,
+}
+
+/// Why a `.tikc` file couldn't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BytecodeError {
+    NotBytecode = 1,
+    UnsupportedVersion = 2,
+    Truncated = 3,
+    Malformed = 4,
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> String {
+        let (phase, number) = match self {
+            ErrorKind::Lex(kind) => (1, *kind as u8),
+            ErrorKind::Parse(kind) => (2, *kind as u8),
+            ErrorKind::Resolve(kind) => (3, *kind as u8),
+            ErrorKind::Runtime(kind) => (4, *kind as u8),
+            ErrorKind::Io => (5, 1),
+            ErrorKind::Compile(kind) => (6, *kind as u8),
+            ErrorKind::Bytecode(kind) => (7, *kind as u8),
+        };
+        format!("E{phase:02}{number:02}")
+    }
+}
+
+/// One active call at the moment a runtime error was raised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub line: usize,
+}
+
+/// The frames a runtime error unwound through, innermost first.
+#[derive(Debug)]
+pub struct StackTrace {
+    pub frames: Vec<Frame>,
+    // Line the next frame out was executing; starts at the error and moves to each call site
+    line: usize,
+}
 
 #[derive(Debug)]
 pub struct TikError {
-    token: Option<Token>,
+    kind: ErrorKind,
+    token: Option<Box<Token>>,
     line: usize,
+    span: Option<Span>,
     message: String,
+    help: Option<Box<str>>,
+    trace: Option<Box<StackTrace>>,
+    // Carried by `throw` while it unwinds
+    value: Option<Box<Object>>,
 }
 
 impl TikError {
-    pub fn error(line: usize, message: String) -> TikError {
-        TikError { token: None, line, message }
+    fn new(kind: ErrorKind, line: usize, span: Option<Span>, message: String) -> TikError {
+        TikError { kind, token: None, line, span, message, help: None, trace: None, value: None }
     }
 
-    pub fn error_parser(token: Token, message: String) -> TikError {
-        let e = TikError {
-            token: Some(token.clone()),
-            line: token.line, 
-            message };
-        e.report("".to_string());
+    fn at_token(kind: ErrorKind, token: Token, message: String) -> TikError {
+        let mut e = TikError::new(kind, token.line, Some(token.span), message);
+        e.token = Some(Box::new(token));
         e
     }
 
-    pub fn runtime_error(token: Token, message: String) -> TikError {
-        let e = TikError {
-            token: Some(token.clone()),
-            line: token.line, 
-            message };
-        e.report("".to_string());
+    pub fn lex_error(kind: LexError, line: usize, span: Span, message: String) -> TikError {
+        TikError::new(ErrorKind::Lex(kind), line, Some(span), message)
+    }
+
+    pub fn error_parser(kind: ParseError, token: Token, message: String) -> TikError {
+        TikError::at_token(ErrorKind::Parse(kind), token, message)
+    }
+
+    pub fn resolve_error(kind: ResolveError, token: Token, message: String) -> TikError {
+        TikError::at_token(ErrorKind::Resolve(kind), token, message)
+    }
+
+    pub fn runtime_error(kind: RuntimeError, token: Token, message: String) -> TikError {
+        TikError::at_token(ErrorKind::Runtime(kind), token, message)
+    }
+
+    /// A runtime error raised where there is no token at hand, such as in the VM,
+    /// which only keeps the line and span of each instruction.
+    pub fn runtime_error_at(kind: RuntimeError, line: usize, span: Span, message: String) -> TikError {
+        TikError::new(ErrorKind::Runtime(kind), line, Some(span), message)
+    }
+
+    pub fn undefined_variable(name: &str, line: usize, span: Span) -> TikError {
+        TikError::runtime_error_at(RuntimeError::UndefinedVariable, line, span, format!("Undefined variable '{name}'"))
+            .with_help(format!("declare it first with `var {name} = ...;`"))
+    }
+
+    pub fn compile_error(kind: CompileError, line: usize, span: Span, message: String) -> TikError {
+        TikError::new(ErrorKind::Compile(kind), line, Some(span), message)
+    }
+
+    pub fn bytecode_error(kind: BytecodeError, message: String) -> TikError {
+        TikError::new(ErrorKind::Bytecode(kind), 0, None, message)
+    }
+
+    pub fn io_error(path: &str, error: &io::Error) -> TikError {
+        TikError::new(ErrorKind::Io, 0, None, format!("Can't read '{path}': {error}"))
+    }
+
+    /// A value raised by `throw`, unwinding until a `catch` takes it.
+    pub fn thrown(line: usize, span: Span, value: Object, message: String) -> TikError {
+        let mut e = TikError::runtime_error_at(RuntimeError::Thrown, line, span, format!("Uncaught exception: {message}"));
+        e.value = Some(Box::new(value));
         e
     }
 
-    pub fn report(&self, loc: String) {
-        if let Some(token) = &self.token {
-            if token.ttype == TokenType::Eof {
-                eprintln!("{} at end {}", token.line, self.message);
-            } else {
-                eprintln!("{} at '{}' {}", token.line, token.lexeme, self.message);
-            }
+    pub fn with_help(mut self, help: String) -> TikError {
+        self.help = Some(help.into_boxed_str());
+        self
+    }
+
+    /// Records that the error unwound out of `function`, which was called from `call_line`.
+    pub fn with_frame(mut self, function: String, call_line: usize) -> TikError {
+        let line = self.line;
+        let trace = self.trace.get_or_insert_with(|| Box::new(StackTrace { frames: Vec::new(), line }));
+        trace.frames.push(Frame { function, line: trace.line });
+        trace.line = call_line;
+        self
+    }
+
+    /// Closes the trace with the top-level frame once the error reaches the script.
+    pub fn with_script_frame(mut self) -> TikError {
+        if let Some(trace) = &mut self.trace {
+            trace.frames.push(Frame { function: "<script>".to_string(), line: trace.line });
+        }
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        self.trace.as_ref().map_or(&[], |trace| &trace.frames)
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Runtime errors and thrown values can be caught.
+    pub fn is_catchable(&self) -> bool {
+        matches!(self.kind, ErrorKind::Runtime(_))
+    }
+
+    /// The value a `throw` is carrying, which has to stay alive while it unwinds.
+    pub fn value(&self) -> Option<&Object> {
+        self.value.as_deref()
+    }
+
+    pub fn into_thrown_value(self) -> Option<Object> {
+        match self.kind {
+            ErrorKind::Runtime(RuntimeError::Thrown) => self.value.map(|value| *value),
+            _ => None,
+        }
+    }
+
+    /// Errors about a whole file, rather than something in it, have no line.
+    fn has_line(&self) -> bool {
+        !matches!(self.kind, ErrorKind::Io | ErrorKind::Bytecode(_))
+    }
+
+    /// Where in the line the error is. Columns start at 1, so a zero one is
+    /// code with no source position, such as a compiler default.
+    fn span(&self) -> Option<Span> {
+        self.span.filter(|span| span.column > 0)
+    }
+
+    /// `file:line:col`, leaving out the column when the error has no position.
+    pub fn location(&self, file: &str) -> String {
+        if let Some(span) = self.span() {
+            format!("{file}:{}:{}", self.line, span.column)
+        } else if !self.has_line() {
+            file.to_string()
         } else {
-            eprintln!("[line {}] Error {}: {}", self.line, loc, self.message);
+            format!("{file}:{}", self.line)
         }
     }
+
+    /// One JSON object on a single line, for tools that consume diagnostics.
+    pub fn to_json(&self, file: &str) -> String {
+        let (column, span) = if let Some(span) = self.span() {
+            (span.column.to_string(), format!("{{\"start\":{},\"end\":{},\"end_column\":{}}}", span.start, span.end, span.end_column))
+        } else {
+            ("null".to_string(), "null".to_string())
+        };
+        let help = self.help.as_deref().map_or("null".to_string(), json_string);
+        let trace: Vec<String> = self.frames().iter()
+            .map(|frame| format!("{{\"function\":{},\"file\":{},\"line\":{}}}", json_string(&frame.function), json_string(file), frame.line))
+            .collect();
+
+        let line = if self.has_line() { self.line.to_string() } else { "null".to_string() };
+
+        format!("{{\"severity\":\"error\",\"code\":\"{}\",\"message\":{},\"file\":{},\"line\":{line},\"column\":{column},\"span\":{span},\"help\":{help},\"trace\":[{}]}}",
+            self.kind.code(), json_string(&self.message), json_string(file), trace.join(","))
+    }
+
+    pub fn report(&self, file: &str, source: &str) {
+        eprint!("{}", self.render(file, source));
+    }
+
+    /// Renders the error the way rustc does: the message, its location, the
+    /// offending source line with the token underlined, and any help note.
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let mut out = format!("error[{}]: {}\n", self.kind.code(), self.message);
+
+        let snippet = self.span().and_then(|span| Snippet::new(source, span));
+        let gutter = " ".repeat(snippet.as_ref().map_or(1, |snippet| snippet.line.to_string().len()));
+
+        if let Some(snippet) = &snippet {
+            out += &format!("{gutter}--> {file}:{}:{}\n", snippet.line, snippet.column);
+            out += &format!("{gutter} |\n");
+            out += &format!("{} | {}\n", snippet.line, snippet.text);
+            let label = match &self.token {
+                Some(token) if token.ttype == TokenType::Eof => " end of input",
+                _ => "",
+            };
+            out += &format!("{gutter} | {}{}{label}\n", snippet.indent(), "^".repeat(snippet.width));
+        } else {
+            out += &format!("{gutter}--> {}\n", self.location(file));
+        }
+
+        if let Some(help) = &self.help {
+            out += &format!("{gutter} = help: {help}\n");
+        }
+
+        if !self.frames().is_empty() {
+            out += "stack backtrace:\n";
+            for (depth, frame) in self.frames().iter().enumerate() {
+                out += &format!("{depth:>4}: {} at {file}:{}\n", frame.function, frame.line);
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for TikError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span() {
+            Some(span) => write!(f, "[{}:{}] error[{}]: {}", self.line, span.column, self.kind.code(), self.message),
+            None => write!(f, "error[{}]: {}", self.kind.code(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for TikError {}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            ch if (ch as u32) < 0x20 => out += &format!("\\u{:04x}", ch as u32),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The single source line an error points into.
+struct Snippet<'a> {
+    line: usize,
+    column: usize,
+    text: &'a str,
+    width: usize,
+}
+
+impl<'a> Snippet<'a> {
+    fn new(source: &'a str, span: Span) -> Option<Snippet<'a>> {
+        let before = source.get(..span.start)?;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..].find('\n').map_or(source.len(), |i| span.start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        // Tokens running past the end of the line are underlined up to it
+        let width = if span.end <= line_end {
+            span.end_column.saturating_sub(span.column)
+        } else {
+            source[span.start..line_end].chars().count()
+        };
+
+        Some(Snippet {
+            line: before.matches('\n').count() + 1,
+            column: span.column,
+            text,
+            width: width.max(1),
+        })
+    }
+
+    /// Whitespace lining the caret up under the token, keeping tabs as tabs.
+    fn indent(&self) -> String {
+        self.text.chars()
+            .take(self.column.saturating_sub(1))
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect()
+    }
 }
@@ -1,11 +1,19 @@
-use tik::define_ast;
 use crate::error::*;
 use crate::token::*;
+use std::cell::Cell;
 
 define_ast!(
     Expr/ExprVisitor,
+    Assign/AssignExpr/visit_assign_expr { name: Token, value: Box<Expr>, depth: Cell<Option<usize>> }
     Binary/BinaryExpr/visit_binary_expr { left: Box<Expr>, operator: Token, right: Box<Expr> }
+    Call/CallExpr/visit_call_expr { callee: Box<Expr>, paren: Token, arguments: Vec<Expr> }
+    Get/GetExpr/visit_get_expr { object: Box<Expr>, name: Token }
     Grouping/GroupingExpr/visit_grouping_expr  { expression: Box<Expr> }
     Literal/LiteralExpr/visit_literal_expr { value: Option<Object> }
+    Logical/LogicalExpr/visit_logical_expr { left: Box<Expr>, operator: Token, right: Box<Expr> }
+    Set/SetExpr/visit_set_expr { object: Box<Expr>, name: Token, value: Box<Expr> }
+    Super/SuperExpr/visit_super_expr { keyword: Token, method: Token, depth: Cell<Option<usize>> }
+    This/ThisExpr/visit_this_expr { keyword: Token, depth: Cell<Option<usize>> }
     Unary/UnaryExpr/visit_unary_expr { operator: Token, right: Box<Expr> }
+    Variable/VariableExpr/visit_variable_expr { name: Token, depth: Cell<Option<usize>> }
     );
@@ -0,0 +1,246 @@
+use crate::token::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::{size_of, size_of_val, ManuallyDrop};
+use std::ops::Deref;
+use std::ptr::NonNull;
+
+/// Collect once the heap has grown this far past what survived the last collection.
+const INITIAL_THRESHOLD: usize = 1024 * 1024;
+
+/// An object that lives on the managed heap, and the references it holds to
+/// other heap objects.
+pub trait Trace {
+    /// Marks every heap object this one refers to.
+    fn trace(&self, tracer: &mut Tracer);
+
+    /// Bytes the object owns outside its own allocation, in maps and vectors.
+    fn extra_size(&self) -> usize {
+        0
+    }
+}
+
+/// The bytes behind a map: one entry and one control byte per slot.
+pub fn map_size<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+pub fn vec_size<T>(vec: &Vec<T>) -> usize {
+    vec.capacity() * size_of::<T>()
+}
+
+struct GcBox<T: ?Sized> {
+    marked: Cell<bool>,
+    // Set under `--gc-stress` on a swept object, whose memory is kept to catch later uses
+    freed: Cell<bool>,
+    // The bytes counted for the object, as of the last collection
+    size: Cell<usize>,
+    value: ManuallyDrop<T>,
+}
+
+/// A reference to an object on the managed heap. Holding one doesn't keep the
+/// object alive; only being reachable from the roots of a collection does.
+pub struct Gc<T: ?Sized + 'static> {
+    object: NonNull<GcBox<T>>,
+}
+
+impl<T: ?Sized> Gc<T> {
+    pub fn ptr_eq(left: &Gc<T>, right: &Gc<T>) -> bool {
+        std::ptr::addr_eq(left.object.as_ptr(), right.object.as_ptr())
+    }
+}
+
+impl<T: ?Sized> Clone for Gc<T> {
+    fn clone(&self) -> Gc<T> {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for Gc<T> {}
+
+impl<T: ?Sized> Deref for Gc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe as long as the object was reachable at every collection since it
+        // was allocated, which is what the backends' roots guarantee
+        let object = unsafe { self.object.as_ref() };
+        debug_assert!(!object.freed.get(), "a collected object was used");
+        &object.value
+    }
+}
+
+impl<T: ?Sized> PartialEq for Gc<T> {
+    fn eq(&self, other: &Gc<T>) -> bool {
+        Gc::ptr_eq(self, other)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// Marks objects during a collection. Each object is traced once, however many
+/// references lead to it.
+pub struct Tracer {
+    gray: Vec<NonNull<GcBox<dyn Trace>>>,
+}
+
+impl Tracer {
+    pub fn mark<T: Trace>(&mut self, object: Gc<T>) {
+        let header = unsafe { object.object.as_ref() };
+        if !header.marked.replace(true) {
+            self.gray.push(object.object);
+        }
+    }
+
+    /// Marks the heap object a value refers to, if it refers to one.
+    pub fn object(&mut self, object: &Object) {
+        match object {
+            Object::Function(function) => self.mark(*function),
+            Object::Class(class) => self.mark(*class),
+            Object::Instance(instance) => self.mark(*instance),
+            Object::Closure(closure) => self.mark(*closure),
+            _ => {},
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Stats {
+    pub allocated: usize,
+    pub freed: usize,
+    pub collections: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "gc: {} bytes allocated, {} bytes freed, {} bytes live, {} collections",
+            self.allocated, self.freed, self.allocated - self.freed, self.collections)
+    }
+}
+
+/// Owns every object allocated and not yet collected.
+///
+/// Allocating never collects, since the caller may be holding objects that
+/// nothing else refers to yet. Instead it marks a collection as due, and the
+/// backends call `collect` at their next safe point, where everything they can
+/// still use is reachable from the roots they mark.
+struct Heap {
+    objects: Vec<NonNull<GcBox<dyn Trace>>>,
+    stats: Stats,
+    next_collection: usize,
+    due: bool,
+    stress: bool,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        objects: Vec::new(),
+        stats: Stats::default(),
+        next_collection: INITIAL_THRESHOLD,
+        due: false,
+        stress: false,
+    });
+}
+
+/// Moves a value onto the managed heap.
+pub fn alloc<T: Trace + 'static>(value: T) -> Gc<T> {
+    let size = size_of::<GcBox<T>>() + value.extra_size();
+    let object = NonNull::from(Box::leak(Box::new(GcBox {
+        marked: Cell::new(false),
+        freed: Cell::new(false),
+        size: Cell::new(size),
+        value: ManuallyDrop::new(value),
+    })));
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.push(object);
+        heap.stats.allocated += size;
+        heap.due = heap.stress || heap.stats.allocated - heap.stats.freed > heap.next_collection;
+    });
+    Gc { object }
+}
+
+/// Collects at the first safe point after every allocation, to shake out
+/// objects the roots miss.
+pub fn set_stress(stress: bool) {
+    HEAP.with(|heap| heap.borrow_mut().stress = stress);
+}
+
+/// Whether the heap has grown enough since the last collection to collect again.
+pub fn due() -> bool {
+    HEAP.with(|heap| heap.borrow().due)
+}
+
+pub fn stats() -> Stats {
+    HEAP.with(|heap| heap.borrow().stats)
+}
+
+/// Frees every object that isn't reachable from what `roots` marks. Anything
+/// the caller still uses must be among those roots or reachable from them.
+pub fn collect(roots: impl FnOnce(&mut Tracer)) {
+    let mut tracer = Tracer { gray: Vec::new() };
+    roots(&mut tracer);
+    while let Some(object) = tracer.gray.pop() {
+        unsafe { object.as_ref() }.value.trace(&mut tracer);
+    }
+
+    HEAP.with(|heap| heap.borrow_mut().sweep());
+}
+
+impl Heap {
+    /// Frees the objects left unmarked and clears the marks on the rest,
+    /// recounting their sizes as their maps and vectors may have grown.
+    fn sweep(&mut self) {
+        let Heap { objects, stats, stress, .. } = self;
+        objects.retain(|&object| {
+            let header = unsafe { object.as_ref() };
+            let size = size_of_val(header) + header.value.extra_size();
+            stats.allocated += size.saturating_sub(header.size.get());
+            stats.freed += header.size.get().saturating_sub(size);
+            header.size.set(size);
+
+            if header.marked.replace(false) {
+                return true;
+            }
+            stats.freed += size;
+            unsafe { free(object, *stress) };
+            false
+        });
+
+        self.stats.collections += 1;
+        self.next_collection = INITIAL_THRESHOLD.max(2 * (self.stats.allocated - self.stats.freed));
+        self.due = false;
+    }
+}
+
+/// Drops an object's value, and releases its memory unless it's kept to catch
+/// uses after the free.
+unsafe fn free(object: NonNull<GcBox<dyn Trace>>, keep: bool) {
+    let header = unsafe { &mut *object.as_ptr() };
+    unsafe { ManuallyDrop::drop(&mut header.value) };
+    if keep {
+        header.freed.set(true);
+    } else {
+        drop(unsafe { Box::from_raw(object.as_ptr()) });
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for &object in &self.objects {
+            unsafe { free(object, false) };
+        }
+    }
+}
@@ -3,220 +3,413 @@ use crate::stmt::*;
 use crate::token::*;
 use crate::token_type::*;
 use crate::error::*;
+use crate::environment::*;
+use crate::callable::*;
+use crate::class::*;
+use crate::gc::{self, Gc};
+use crate::symbol::*;
+use crate::operator::{self, is_truthy};
+use std::collections::HashMap;
+use crate::vm::FRAMES_MAX;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 
-pub struct Interpreter {}
+/// Native stack the calls into Tik functions may take up between them. The
+/// rest of the 8 MiB a main thread usually gets is left for the innermost
+/// call, whose expressions the parser keeps from nesting too deeply.
+const STACK_LIMIT: usize = 4 * 1024 * 1024;
 
-impl StmtVisitor<()> for Interpreter {
-    fn visit_expression_stmt(&self, expr: &ExpressionStmt) -> Result<(),TikError> {
+/// How a statement stops short: a `return` carrying its value out to the call,
+/// or an error unwinding until something catches it.
+pub enum Unwind {
+    Return(Object),
+    Error(TikError),
+}
+
+impl From<TikError> for Unwind {
+    fn from(e: TikError) -> Unwind {
+        Unwind::Error(e)
+    }
+}
+
+pub struct Interpreter {
+    globals: Gc<RefCell<Environment>>,
+    environment: Cell<Gc<RefCell<Environment>>>,
+    // Class of the objects `catch` binds for runtime errors and thrown non-instances
+    error_class: Gc<TikClass>,
+    // The scopes of the blocks the current one is nested in, which are still in use
+    suspended: RefCell<Vec<Gc<RefCell<Environment>>>>,
+    // Values the interpreter holds on to while evaluating something else, which
+    // could run a collection
+    held: RefCell<Vec<Object>>,
+    // Calls into Tik functions currently running, checked against `FRAMES_MAX`
+    depth: Cell<usize>,
+    // Where the native stack was when the script started, to check against `STACK_LIMIT`
+    stack_base: Cell<usize>,
+}
+
+impl StmtVisitor<(), Unwind> for Interpreter {
+    fn visit_block_stmt(&self, stmt: &BlockStmt) -> Result<(), Unwind> {
+        let environment = Environment::new_enclosing(self.environment.get());
+        self.execute_block(&stmt.statements, gc::alloc(RefCell::new(environment)))
+    }
+
+    fn visit_class_stmt(&self, stmt: &ClassStmt) -> Result<(), Unwind> {
+        let superclass = if let Some(superclass) = &stmt.superclass {
+            match superclass.accept(self)? {
+                Object::Class(class) => Some(class),
+                _ => return Err(TikError::runtime_error(RuntimeError::SuperclassNotClass, superclass.name.clone(), "Superclass must be a class".to_string()).into()),
+            }
+        } else {
+            None
+        };
+
+        self.environment.get().borrow_mut().define(stmt.name.lexeme.clone(), Object::Nil);
+
+        // Methods of a subclass close over an extra scope that binds `super`
+        let enclosing = if let Some(superclass) = &superclass {
+            let mut environment = Environment::new_enclosing(self.environment.get());
+            environment.define(Symbol::intern("super"), Object::Class(*superclass));
+            Some(self.environment.replace(gc::alloc(RefCell::new(environment))))
+        } else {
+            None
+        };
+
+        let mut methods = HashMap::new();
+        for method in &stmt.methods {
+            let is_initializer = method.name.lexeme == "init";
+            let function = TikFunction::new(method, self.environment.get(), is_initializer);
+            methods.insert(method.name.lexeme.clone(), Method::Function(gc::alloc(function)));
+        }
+
+        let class = Object::Class(gc::alloc(TikClass::new(stmt.name.lexeme.to_string(), superclass, methods)));
+
+        if let Some(enclosing) = enclosing {
+            self.environment.replace(enclosing);
+        }
+        Ok(self.environment.get().borrow_mut().assign(&stmt.name, class)?)
+    }
+
+    fn visit_expression_stmt(&self, expr: &ExpressionStmt) -> Result<(), Unwind> {
        self.evaluate(&expr.expression)?;
        Ok(())
     }
 
-    fn visit_print_stmt(&self,expr: &PrintStmt) -> Result<(),TikError> {
+    fn visit_function_stmt(&self, stmt: &FunctionStmt) -> Result<(), Unwind> {
+        let function = Object::Function(gc::alloc(TikFunction::new(stmt, self.environment.get(), false)));
+        self.environment.get().borrow_mut().define(stmt.name.lexeme.clone(), function);
+        Ok(())
+    }
+
+    fn visit_if_stmt(&self, stmt: &IfStmt) -> Result<(), Unwind> {
+        if is_truthy(&self.evaluate(&stmt.condition)?) {
+            self.execute(&stmt.then_branch)
+        } else if let Some(else_branch) = &stmt.else_branch {
+            self.execute(else_branch)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn visit_print_stmt(&self,expr: &PrintStmt) -> Result<(), Unwind> {
         let value = self.evaluate(&expr.expression)?;
         println!("{value}");
         Ok(()) 
     }
+
+    fn visit_return_stmt(&self, stmt: &ReturnStmt) -> Result<(), Unwind> {
+        let value = if let Some(value) = &stmt.value {
+            self.evaluate(value)?
+        } else {
+            Object::Nil
+        };
+
+        Err(Unwind::Return(value))
+    }
+
+    fn visit_throw_stmt(&self, stmt: &ThrowStmt) -> Result<(), Unwind> {
+        let value = self.evaluate(&stmt.value)?;
+        let message = value.describe();
+        Err(TikError::thrown(stmt.keyword.line, stmt.keyword.span, value, message).into())
+    }
+
+    fn visit_try_stmt(&self, stmt: &TryStmt) -> Result<(), Unwind> {
+        let result = match (self.execute_block(&stmt.body, self.new_scope()), &stmt.catch_clause) {
+            (Err(Unwind::Error(e)), Some(catch_clause)) if e.is_catchable() => {
+                let scope = self.new_scope();
+                scope.borrow_mut().define(catch_clause.name.lexeme.clone(), TikInstance::from_error(self.error_class, e));
+                self.execute_block(&catch_clause.body, scope)
+            },
+            (result, _) => result,
+        };
+
+        // A failing `finally` replaces whatever the try or catch was unwinding with
+        if let Some(finally_body) = &stmt.finally_body {
+            let value = match &result {
+                Err(Unwind::Return(value)) => value.clone(),
+                Err(Unwind::Error(e)) => e.value().cloned().unwrap_or(Object::Nil),
+                Ok(()) => Object::Nil,
+            };
+            self.holding(&value, || self.execute_block(finally_body, self.new_scope()))?;
+        }
+        result
+    }
+
+    fn visit_var_stmt(&self, stmt: &VarStmt) -> Result<(), Unwind> {
+        let value = if let Some(initializer) = &stmt.initializer {
+            self.evaluate(initializer)?
+        } else {
+            Object::Nil
+        };
+
+        self.environment.get().borrow_mut().define(stmt.name.lexeme.clone(), value);
+        Ok(())
+    }
+
+    fn visit_while_stmt(&self, stmt: &WhileStmt) -> Result<(), Unwind> {
+        while is_truthy(&self.evaluate(&stmt.condition)?) {
+            self.execute(&stmt.body)?;
+        }
+        Ok(())
+    }
 }
 
 impl ExprVisitor<Object> for Interpreter {
+    fn visit_assign_expr(&self, expr: &AssignExpr) -> Result<Object, TikError> {
+        let value = self.evaluate(&expr.value)?;
+
+        if let Some(distance) = expr.depth.get() {
+            self.environment.get().borrow_mut().assign_at(distance, &expr.name, value.clone())?;
+        } else {
+            self.globals.borrow_mut().assign(&expr.name, value.clone())?;
+        }
+        Ok(value)
+    }
+
+    fn visit_variable_expr(&self, expr: &VariableExpr) -> Result<Object, TikError> {
+        self.look_up_variable(&expr.name, expr.depth.get())
+    }
+
     fn visit_literal_expr(&self,expr: &LiteralExpr) -> Result<Object, TikError> {
         Ok(expr.value.clone().unwrap())
     }
 
+    fn visit_call_expr(&self, expr: &CallExpr) -> Result<Object, TikError> {
+        let callee = self.evaluate(&expr.callee)?;
+
+        // The callee and each argument are held until the call returns
+        let held = self.held.borrow().len();
+        self.held.borrow_mut().push(callee.clone());
+        let result = self.call(&callee, expr);
+        self.held.borrow_mut().truncate(held);
+        result
+    }
+
+    fn visit_get_expr(&self, expr: &GetExpr) -> Result<Object, TikError> {
+        match self.evaluate(&expr.object)? {
+            Object::Instance(instance) => TikInstance::get(instance, &expr.name),
+            _ => Err(TikError::runtime_error(RuntimeError::NotAnInstance, expr.name.clone(), "Only instances have properties".to_string())),
+        }
+    }
+
     fn visit_grouping_expr(&self, expr: &GroupingExpr) -> Result<Object, TikError> {
         self.evaluate(&expr.expression)
     }
 
-    fn visit_unary_expr(&self,expr: &UnaryExpr) -> Result<Object, TikError> {
-        let right = self.evaluate(&expr.right)?;
-        
-        match expr.operator.ttype {
-            TokenType::Minus => match right {
-                Object::Num(number) => {
-                    return Ok(Object::Num(-number));
-                },
-                _ => { 
-                    return Ok(Object::Nil);
-                },
-            } ,
-            TokenType::Bang => if self.is_truthy(&right) {
-                return Ok(Object::False);
-            } else {
-                return Ok(Object::True);
-            },
-            _ => {
-                return Err(TikError::error(expr.operator.line, "Unreachable".to_string()));
-            },
+    fn visit_set_expr(&self, expr: &SetExpr) -> Result<Object, TikError> {
+        let object = self.evaluate(&expr.object)?;
+        let Object::Instance(instance) = object else {
+            return Err(TikError::runtime_error(RuntimeError::NotAnInstance, expr.name.clone(), "Only instances have fields".to_string()));
+        };
+
+        let value = self.holding(&object, || self.evaluate(&expr.value))?;
+        instance.set(&expr.name, value.clone());
+        Ok(value)
+    }
+
+    fn visit_super_expr(&self, expr: &SuperExpr) -> Result<Object, TikError> {
+        let (Some(distance), Object::Class(superclass)) = (expr.depth.get(), self.look_up_variable(&expr.keyword, expr.depth.get())?) else {
+            return Err(TikError::runtime_error(RuntimeError::UndefinedVariable, expr.keyword.clone(), "Can't use 'super' outside of a subclass".to_string()));
+        };
+
+        // `this` is always bound in the scope just inside the one holding `super`
+        let this = Token::new(TokenType::This, Symbol::intern("this"), None, expr.keyword.line, expr.keyword.span);
+        let object = self.environment.get().borrow().get_at(distance - 1, &this)?;
+
+        if let Some(method) = superclass.find_method(&expr.method.lexeme) {
+            Ok(method.bind(object))
+        } else {
+            Err(TikError::runtime_error(RuntimeError::UndefinedProperty, expr.method.clone(), format!("Undefined property '{}'", expr.method.lexeme)))
         }
     }
 
-    fn visit_binary_expr(&self, expr: &BinaryExpr) -> Result<Object, TikError> {
+    fn visit_this_expr(&self, expr: &ThisExpr) -> Result<Object, TikError> {
+        self.look_up_variable(&expr.keyword, expr.depth.get())
+    }
+
+    fn visit_logical_expr(&self, expr: &LogicalExpr) -> Result<Object, TikError> {
         let left = self.evaluate(&expr.left)?;
+
+        if expr.operator.ttype == TokenType::Or {
+            if is_truthy(&left) {
+                return Ok(left);
+            }
+        } else if !is_truthy(&left) {
+            return Ok(left);
+        }
+
+        self.evaluate(&expr.right)
+    }
+
+    fn visit_unary_expr(&self, expr: &UnaryExpr) -> Result<Object, TikError> {
         let right = self.evaluate(&expr.right)?;
+        operator::unary(expr.operator.ttype, right).map_err(|e| e.at(expr.operator.line, expr.operator.span))
+    }
 
-        let result: Result<Object, TikError> = match expr.operator.ttype {
-            TokenType::Greater => {
-            /*
-                match (left, right) {
-                    (Object::Num(left), Object::Num(right)) => {
-                        if left > right {
-                            return Ok(Object::True);
-                        } else {
-                            return Ok(Object::False);
-                        }
-                    },
-                    _ => return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));,
-                }
-                */
-
-                if let Object::Num(left) = left {
-                    if let Object::Num(right) = right {
-                        if left > right {
-                            return Ok(Object::True);
-                        } else {
-                            return Ok(Object::False);
-                        }
-                    }
-                }
-                return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-            },
-            TokenType::GreaterEqual => {
-                if let Object::Num(left) = left {
-                    if let Object::Num(right) = right {
-                        if left >= right {
-                            return Ok(Object::True);
-                        } else {
-                            return Ok(Object::False);
-                        }
-                    }
-                }
-                return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-            },
-            TokenType::Less => {
-                if let Object::Num(left) = left {
-                    if let Object::Num(right) = right {
-                        if left < right {
-                            return Ok(Object::True);
-                        } else {
-                            return Ok(Object::False);
-                        }
-                    }
-                }
-                return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-            },
-            TokenType::LessEqual=> {
-                if let Object::Num(left) = left {
-                    if let Object::Num(right) = right {
-                        if left <= right {
-                            return Ok(Object::True);
-                        } else {
-                            return Ok(Object::False);
-                        }
-                    }
-                }
-                return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-            },
-            TokenType::Bang => {
-                Ok(Object::Nil)
-            },
-            TokenType::BangEqual => {
-                if let Object::Num(left) = left {
-                    if let Object::Num(right) = right {
-                        if left != right {
-                            return Ok(Object::True);
-                        } else {
-                            return Ok(Object::False);
-                        }
-                    }
-                }
-                return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-            },
-            TokenType::EqualEqual => {
-                if let Object::Num(left) = left {
-                    if let Object::Num(right) = right {
-                        if left == right {
-                            return Ok(Object::True);
-                        } else {
-                            return Ok(Object::False);
-                        }
-                    }
-                }
-                return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-            },
-            TokenType::Minus => {
-                let result = left - right;
-                if result == Object::ArithmeticException {
-                    return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-                } else {
-                    return Ok(result);
-                }
-            },
-            TokenType::Plus => {
-                let result = left + right;
-                if result == Object::ArithmeticException {
-                    return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-                } else {
-                    return Ok(result);
-                }
-            },
-            TokenType::Slash=> {
-                let result = left / right;
-                if result == Object::ArithmeticException {
-                    return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-                } else {
-                    return Ok(result);
-                }
-            },
-            TokenType::Star => {
-                let result = left * right;
-                if result == Object::ArithmeticException {
-                    return Err(TikError::error(expr.operator.line, "Invalid expression".to_string()));
-                } else {
-                    return Ok(result);
-                }
-            },
-            _ => {
-                return Err(TikError::error(expr.operator.line, "Unreachable".to_string()));
-            },
-        };
+    fn visit_binary_expr(&self, expr: &BinaryExpr) -> Result<Object, TikError> {
+        let left = self.evaluate(&expr.left)?;
+        let right = self.holding(&left, || self.evaluate(&expr.right))?;
+        operator::binary(expr.operator.ttype, left, right).map_err(|e| e.at(expr.operator.line, expr.operator.span))
+    }
+}
 
-        result
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
     }
 }
 
 impl Interpreter {
+    pub fn new() -> Interpreter {
+        let globals = gc::alloc(RefCell::new(Environment::new()));
+
+        for native in NativeFunction::globals() {
+            globals.borrow_mut().define(Symbol::from(native.name.clone()), Object::Native(Rc::new(native)));
+        }
+
+        Interpreter {
+            environment: Cell::new(globals),
+            globals,
+            error_class: gc::alloc(TikClass::error()),
+            suspended: RefCell::new(Vec::new()),
+            held: RefCell::new(Vec::new()),
+            depth: Cell::new(0),
+            stack_base: Cell::new(0),
+        }
+    }
+
+    fn new_scope(&self) -> Gc<RefCell<Environment>> {
+        gc::alloc(RefCell::new(Environment::new_enclosing(self.environment.get())))
+    }
+
     fn evaluate(&self, expr: &Expr) -> Result<Object, TikError> {
         expr.accept(self)
     }
 
-    fn is_truthy(&self, object: &Object) -> bool {
-        match object {
-            Object::Nil |
-            Object::False => { return false; },
-            _ => { return true; },
+    fn look_up_variable(&self, name: &Token, depth: Option<usize>) -> Result<Object, TikError> {
+        if let Some(distance) = depth {
+            self.environment.get().borrow().get_at(distance, name)
+        } else {
+            self.globals.borrow().get(name)
         }
     }
 
-    fn execute(&self, stmt: &Stmt) -> Result<(), TikError> {
+    fn execute(&self, stmt: &Stmt) -> Result<(), Unwind> {
+        // Between statements, everything in use is reachable from the roots
+        if gc::due() {
+            self.collect_garbage();
+        }
         stmt.accept(self)
     }
 
-    pub fn interpret(&self, stmts: &[Stmt]) -> bool {
-        let mut succ = true;
-        for stmt in stmts {
-            if let Err(e) = self.execute(stmt) {
-                e.report("".to_string());
-                succ = false;
-                break;
+    pub fn execute_block(&self, statements: &[Stmt], environment: Gc<RefCell<Environment>>) -> Result<(), Unwind> {
+        let previous = self.environment.replace(environment);
+        self.suspended.borrow_mut().push(previous);
+
+        let result = statements.iter().try_for_each(|statement| self.execute(statement));
+
+        self.suspended.borrow_mut().pop();
+        self.environment.set(previous);
+        result
+    }
+
+    /// Runs `f` with `value` kept alive through any collection it triggers.
+    fn holding<T>(&self, value: &Object, f: impl FnOnce() -> T) -> T {
+        self.held.borrow_mut().push(value.clone());
+        let result = f();
+        self.held.borrow_mut().pop();
+        result
+    }
+
+    fn call(&self, callee: &Object, expr: &CallExpr) -> Result<Object, TikError> {
+        let mut arguments = Vec::new();
+        for argument in &expr.arguments {
+            let value = self.evaluate(argument)?;
+            self.held.borrow_mut().push(value.clone());
+            arguments.push(value);
+        }
+
+        let function: &dyn TikCallable = match callee {
+            Object::Function(function) => &**function,
+            Object::Native(function) => function.as_ref(),
+            Object::Class(class) => class,
+            _ => return Err(TikError::runtime_error(RuntimeError::NotCallable, expr.paren.clone(), "Can only call functions and classes".to_string())),
+        };
+
+        if arguments.len() != function.arity() {
+            return Err(TikError::runtime_error(RuntimeError::ArityMismatch, expr.paren.clone(),
+                format!("Expected {} arguments but got {}", function.arity(), arguments.len())));
+        }
+
+        // Natives and classes without an initializer don't run a body, so only
+        // the calls the VM would push a frame for count towards the limit
+        let nested = match callee {
+            Object::Function(_) => true,
+            Object::Class(class) => class.find_method(&Symbol::intern("init")).is_some(),
+            _ => false,
+        };
+        // Each call recurses on the native stack, which runs out before
+        // `FRAMES_MAX` calls in an unoptimized build
+        if nested && (self.depth.get() >= FRAMES_MAX || self.stack_base.get().abs_diff(stack_address()) > STACK_LIMIT) {
+            return Err(TikError::runtime_error(RuntimeError::StackOverflow, expr.paren.clone(), "Stack overflow".to_string()));
+        }
+
+        self.depth.set(self.depth.get() + nested as usize);
+        let result = function.call(self, arguments);
+        self.depth.set(self.depth.get() - nested as usize);
+        result.map_err(|e| e.with_frame(function.name(), expr.paren.line))
+    }
+
+    /// Collects every heap object the interpreter can no longer reach.
+    pub fn collect_garbage(&self) {
+        gc::collect(|tracer| {
+            tracer.mark(self.globals);
+            tracer.mark(self.environment.get());
+            tracer.mark(self.error_class);
+            for environment in self.suspended.borrow().iter() {
+                tracer.mark(*environment);
             }
-            /*
+            for value in self.held.borrow().iter() {
+                tracer.object(value);
+            }
+        });
+    }
+
+    pub fn interpret(&self, stmts: &[Stmt]) -> Result<(), TikError> {
+        self.stack_base.set(stack_address());
+        for stmt in stmts {
             match self.execute(stmt) {
-                Ok(v) => { println!("{}", v); continue; },
-                Err(e) => { e .report("".to_string()); succ = false; break; },
+                Ok(()) => {},
+                Err(Unwind::Error(e)) => return Err(e.with_script_frame()),
+                Err(Unwind::Return(_)) => unreachable!("the resolver rejects a return outside a function"),
             }
-            */
         }
-       succ 
+        Ok(())
     }
 }
+
+/// Roughly how far the native stack has grown: the address of a local.
+fn stack_address() -> usize {
+    let local = 0u8;
+    std::hint::black_box(&local) as *const u8 as usize
+}
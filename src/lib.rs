@@ -8,7 +8,7 @@ macro_rules! define_ast {
         }
 
         impl $type {
-            pub fn accept<T>(&self, visitor: &dyn $visitor<T>) -> Result<T, TikError> {
+            pub fn accept<T, E>(&self, visitor: &dyn $visitor<T, E>) -> Result<T, E> {
                 match self {
                     $(
                         $type::$choice(x) => x.accept(visitor),
@@ -25,18 +25,42 @@ macro_rules! define_ast {
             }
          )*
 
-            pub trait $visitor<T> {
+            pub trait $visitor<T, E = TikError> {
                 $(
-                    fn $visit(&self, expr: &$struct) -> Result<T, TikError>;
+                    fn $visit(&self, expr: &$struct) -> Result<T, E>;
                  )*
             }
 
         $(
             impl $struct {
-                pub fn accept<T>(&self, visitor: &dyn $visitor<T>) -> Result<T, TikError> {
+                pub fn accept<T, E>(&self, visitor: &dyn $visitor<T, E>) -> Result<T, E> {
                     visitor.$visit(self)
                 }
             }
          )*
     }
 }
+
+pub mod error;
+pub mod token_type;
+pub mod token;
+pub mod scanner;
+pub mod expr;
+pub mod astprinter;
+pub mod parser;
+pub mod interpreter;
+pub mod stmt;
+pub mod environment;
+pub mod callable;
+pub mod class;
+pub mod resolver;
+pub mod operator;
+pub mod chunk;
+pub mod compiler;
+pub mod vm;
+pub mod disassembler;
+pub mod bytecode;
+pub mod gc;
+pub mod symbol;
+pub mod optimizer;
+pub mod bigint;
@@ -1,67 +1,212 @@
-mod error;
-mod token_type;
-mod token;
-mod scanner;
-mod expr;
-mod astprinter;
-mod parser;
-mod interpreter;
-mod stmt;
-
-use error::*;
-use scanner::*;
-use expr::*;
-use astprinter::*;
-use parser::*;
-use token::*;
-use interpreter::*;
+use tik::error::*;
+use tik::scanner::*;
+use tik::parser::*;
+use tik::interpreter::*;
+use tik::resolver::*;
+use tik::compiler::*;
+use tik::vm::*;
+use tik::stmt::*;
+use tik::chunk::*;
+use tik::{bytecode, gc};
+use tik::disassembler::*;
+use tik::optimizer::*;
+use tik::astprinter::*;
 
 use std::io::{self, stdout, Write, BufRead};
 use std::fs;
+use std::path::Path;
 use std::env::args;
 
-
-
 fn main() {
-    let args: Vec<String> = args().collect();
-    let  mut tik = Tik::new();
+    let mut tik = Tik::new();
+    let mut scripts = Vec::new();
 
-    println!("args: {:?}", args);
+    for arg in args().skip(1) {
+        if let Some(backend) = arg.strip_prefix("--backend=") {
+            tik.backend = match backend {
+                "tree" => Backend::Tree(Interpreter::new()),
+                "vm" => Backend::Vm(Vm::new()),
+                _ => Tik::usage(),
+            };
+        } else if let Some(format) = arg.strip_prefix("--error-format=") {
+            tik.error_format = match format {
+                "human" => ErrorFormat::Human,
+                "json" => ErrorFormat::Json,
+                _ => Tik::usage(),
+            };
+        } else if let Some(emit) = arg.strip_prefix("--emit=") {
+            tik.emit = match emit {
+                "ast" => Some(Emit::Ast),
+                "optimized-ast" => Some(Emit::OptimizedAst),
+                _ => Tik::usage(),
+            };
+        } else if arg == "-O" {
+            tik.optimize = true;
+        } else if arg == "--gc-stress" {
+            gc::set_stress(true);
+        } else if arg == "--gc-stats" {
+            tik.gc_stats = true;
+        } else if arg.starts_with('-') {
+            Tik::usage();
+        } else {
+            scripts.push(arg);
+        }
+    }
 
-    if args.len() > 2 {
-        println!("Usage: tik [script]");
-        std::process::exit(64);
-    } else if args.len() == 2 {
-        tik.run_file(&args[1]).expect("run_file failed");
-    } else {
-        tik.run_prompt();
+    match scripts.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["compile", script] => tik.compile_file(script, &Path::new(script).with_extension("tikc").to_string_lossy()),
+        ["compile", script, output] => tik.compile_file(script, output),
+        ["run", file] | [file] if file.ends_with(".tikc") => tik.run_compiled(file),
+        ["run", script] | [script] => tik.run_file(script),
+        ["disassemble", file] => tik.disassemble_file(file),
+        [] => tik.run_prompt(),
+        _ => Tik::usage(),
     }
+    tik.exit(0);
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
+
+/// A stage of the pipeline to print instead of running the script.
+#[derive(Clone, Copy, PartialEq)]
+enum Emit {
+    Ast,
+    OptimizedAst,
+}
+
+/// What runs a script once it has been parsed and resolved: the tree-walking
+/// interpreter, or the bytecode compiler and VM.
+enum Backend {
+    Tree(Interpreter),
+    Vm(Vm),
+}
 
 struct Tik {
-    interpreter: Interpreter,
+    backend: Backend,
+    error_format: ErrorFormat,
+    // Report what the collector did on exit
+    gc_stats: bool,
+    // Fold constant expressions before running or compiling
+    optimize: bool,
+    emit: Option<Emit>,
 }
 
 impl Tik {
     pub fn new() -> Tik {
-        Tik { interpreter: Interpreter {} }
+        Tik { backend: Backend::Tree(Interpreter::new()), error_format: ErrorFormat::Human, gc_stats: false, optimize: false, emit: None }
+    }
+
+    fn usage() -> ! {
+        println!("Usage: tik [--backend=tree|vm] [--error-format=human|json] [-O] [--emit=ast|optimized-ast]");
+        println!("           [--gc-stress] [--gc-stats] [script]");
+        println!("       tik [--error-format=human|json] [-O] compile <script> [output.tikc]");
+        println!("       tik [--error-format=human|json] run <script|file.tikc>");
+        println!("       tik [--error-format=human|json] disassemble <script|file.tikc>");
+        std::process::exit(64);
+    }
+
+    pub fn run_file(&mut self, path: &str) {
+        let buffer = self.read_source(path);
+
+        if let Err(errors) = self.run(&buffer) {
+            self.report(path, &buffer, &errors);
+            self.exit(Tik::exit_code(&errors));
+        }
+    }
+
+    /// Compiles a script to bytecode and saves it, so `run_compiled` can run it
+    /// later without scanning or parsing it again.
+    pub fn compile_file(&self, path: &str, output: &str) {
+        let buffer = self.read_source(path);
+
+        let function = match self.compile(&buffer) {
+            Ok(function) => function,
+            Err(errors) => {
+                self.report(path, &buffer, &errors);
+                self.exit(Tik::exit_code(&errors));
+            }
+        };
+
+        if let Err(e) = fs::write(output, bytecode::write(path, &function)) {
+            self.report(output, "", &[TikError::io_error(output, &e)]);
+            self.exit(66);
+        }
     }
 
-    pub fn run_file(&self, path: &String) -> io::Result<()>{
-        let buffer = fs::read_to_string(path)?;
+    /// Runs a `.tikc` file on the VM whichever backend was asked for. Runtime
+    /// errors are reported against the script it was compiled from, when that's
+    /// still around to quote.
+    pub fn run_compiled(&mut self, path: &str) {
+        let compiled = self.read_compiled(path);
+        let source = fs::read_to_string(&compiled.source).unwrap_or_default();
 
-        match self.run(buffer) {
-            Ok(_) => {},
-            Err(_) => {
-                std::process::exit(65);
+        // The VM becomes the backend so that collecting on exit marks its roots
+        let mut vm = Vm::new();
+        let result = vm.interpret(compiled.function);
+        self.backend = Backend::Vm(vm);
+        if let Err(e) = result {
+            self.report(&compiled.source, &source, &[e]);
+            self.exit(70);
+        }
+    }
+
+    pub fn disassemble_file(&self, path: &str) {
+        let function = if path.ends_with(".tikc") {
+            self.read_compiled(path).function
+        } else {
+            let buffer = self.read_source(path);
+            self.compile(&buffer).unwrap_or_else(|errors| {
+                self.report(path, &buffer, &errors);
+                self.exit(Tik::exit_code(&errors));
+            })
+        };
+        print!("{}", disassemble(&function));
+    }
+
+    fn read_source(&self, path: &str) -> String {
+        fs::read_to_string(path).unwrap_or_else(|e| {
+            self.report(path, "", &[TikError::io_error(path, &e)]);
+            self.exit(66);
+        })
+    }
+
+    fn read_compiled(&self, path: &str) -> bytecode::Compiled {
+        let bytes = fs::read(path).unwrap_or_else(|e| {
+            self.report(path, "", &[TikError::io_error(path, &e)]);
+            self.exit(66);
+        });
+        bytecode::read(&bytes, path).unwrap_or_else(|e| {
+            self.report(path, "", &[e]);
+            self.exit(65);
+        })
+    }
+
+    fn exit(&self, code: i32) -> ! {
+        if self.gc_stats {
+            match &self.backend {
+                Backend::Tree(interpreter) => interpreter.collect_garbage(),
+                Backend::Vm(vm) => vm.collect_garbage(),
             }
+            eprintln!("{}", gc::stats());
         }
+        std::process::exit(code);
+    }
 
-        Ok(())
+    /// Follows sysexits: 65 for a script that doesn't compile, 70 for one that
+    /// fails while running and 66 for one that can't be read.
+    fn exit_code(errors: &[TikError]) -> i32 {
+        match errors.first().map(|e| e.kind()) {
+            Some(ErrorKind::Runtime(_)) => 70,
+            Some(ErrorKind::Io) => 66,
+            _ => 65,
+        }
     }
 
-    pub fn run_prompt(&self) {
+    pub fn run_prompt(&mut self) {
         let stdin = io::stdin();
 
         print!("> ");
@@ -72,10 +217,8 @@ impl Tik {
                 if line.is_empty() {
                     break;
                 }
-                match self.run(line) {
-                    Ok(_) => {},
-                    Err(_) => {
-                    },
+                if let Err(errors) = self.run(&line) {
+                    self.report("<stdin>", &line, &errors);
                 }
             } else {
                 break;
@@ -85,38 +228,66 @@ impl Tik {
         }
     }
 
-    pub fn run(&self, source: String) -> Result<(), TikError> {
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens()?;
-        let mut parser = Parser::new(tokens.to_vec());
-        let statments = parser.parse()?;
+    pub fn run(&mut self, source: &str) -> Result<(), Vec<TikError>> {
+        let statments = self.parse(source)?;
 
-        if self.interpreter.interpret(&statments) {
+        if self.emit.is_some() {
+            for statement in &statments {
+                println!("{}", AstPrinter.print_stmt(statement).map_err(|e| vec![e])?);
+            }
             return Ok(());
-        } else {
-            return Err(TikError::error(0, "".to_string())); 
         }
 
-        /*
-        match parser.parse() {
-            Ok(expr) => {
-                self.interpreter.interpret(&expr);
-                /*
-                let printer = AstPrinter {};
-                let p = printer.print(&expr)?;
-                println!("{}", p);
-                */
+        match &mut self.backend {
+            Backend::Tree(interpreter) => interpreter.interpret(&statments).map_err(|e| vec![e]),
+            Backend::Vm(vm) => {
+                let function = Compiler::new().compile(&statments).map_err(|e| vec![e])?;
+                vm.interpret(function).map_err(|e| vec![e])
             },
-            Err(_) => { },
         }
+    }
 
-        /*
-        for token in tokens {
-            println!("Token: {:?}", token);
+    /// Scans, parses, optimizes when asked to and resolves a script, the part
+    /// every backend shares.
+    fn parse(&self, source: &str) -> Result<Vec<Stmt>, Vec<TikError>> {
+        let (tokens, mut errors) = Scanner::new(source).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let (statments, parse_errors) = parser.parse();
+
+        // Report lex and parse errors together, in the order they appear
+        errors.extend(parse_errors);
+        if !errors.is_empty() {
+            errors.sort_by_key(|e| e.line());
+            return Err(errors);
         }
-        */
 
-        Ok(())
-    */
+        let optimize = match self.emit {
+            Some(Emit::Ast) => false,
+            Some(Emit::OptimizedAst) => true,
+            None => self.optimize,
+        };
+        let statments = if optimize {
+            Optimizer.optimize(&statments).map_err(|e| vec![e])?
+        } else {
+            statments
+        };
+
+        let resolver = Resolver::new();
+        resolver.resolve(&statments)?;
+        Ok(statments)
+    }
+
+    fn compile(&self, source: &str) -> Result<Function, Vec<TikError>> {
+        let statments = self.parse(source)?;
+        Compiler::new().compile(&statments).map_err(|e| vec![e])
+    }
+
+    fn report(&self, file: &str, source: &str, errors: &[TikError]) {
+        for e in errors {
+            match self.error_format {
+                ErrorFormat::Human => e.report(file, source),
+                ErrorFormat::Json => eprintln!("{}", e.to_json(file)),
+            }
+        }
     }
 }
@@ -0,0 +1,124 @@
+use crate::error::*;
+use crate::token::*;
+use crate::token_type::*;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use crate::gc::Gc;
+
+/// An operator applied to operands it doesn't support. Each backend turns it
+/// into a runtime error at the operator's location.
+pub struct OperandError {
+    kind: RuntimeError,
+    message: String,
+    help: Option<String>,
+}
+
+impl OperandError {
+    fn invalid(operator: TokenType) -> OperandError {
+        let help = match operator {
+            TokenType::Plus => Some("'+' adds two numbers or concatenates two strings".to_string()),
+            _ => None,
+        };
+        OperandError { kind: RuntimeError::InvalidOperands, message: "Invalid expression".to_string(), help }
+    }
+
+    fn division_by_zero() -> OperandError {
+        OperandError { kind: RuntimeError::DivisionByZero, message: "Division by zero".to_string(), help: None }
+    }
+
+    fn integer_too_large() -> OperandError {
+        OperandError { kind: RuntimeError::IntegerTooLarge, message: "Integer too large".to_string(), help: None }
+    }
+
+    fn unsupported() -> OperandError {
+        OperandError { kind: RuntimeError::UnsupportedOperator, message: "Unreachable".to_string(), help: None }
+    }
+
+    pub fn at(self, line: usize, span: Span) -> TikError {
+        let e = TikError::runtime_error_at(self.kind, line, span, self.message);
+        match self.help {
+            Some(help) => e.with_help(help),
+            None => e,
+        }
+    }
+}
+
+/// Evaluates a binary operator. Both backends go through here so they accept
+/// and reject exactly the same operands.
+pub fn binary(operator: TokenType, left: Object, right: Object) -> Result<Object, OperandError> {
+    let result = match operator {
+        TokenType::Greater => compare(left, right, Ordering::is_gt),
+        TokenType::GreaterEqual => compare(left, right, Ordering::is_ge),
+        TokenType::Less => compare(left, right, Ordering::is_lt),
+        TokenType::LessEqual => compare(left, right, Ordering::is_le),
+        TokenType::BangEqual => boolean(!equal(&left, &right)),
+        TokenType::EqualEqual => boolean(equal(&left, &right)),
+        TokenType::Minus => left - right,
+        TokenType::Plus => left + right,
+        TokenType::Slash => left / right,
+        TokenType::Star => left * right,
+        TokenType::Percent => left % right,
+        TokenType::TildeSlash => left.floor_div(right),
+        TokenType::StarStar => left.pow(right),
+        _ => return Err(OperandError::unsupported()),
+    };
+
+    match result {
+        Object::ArithmeticException => Err(OperandError::invalid(operator)),
+        Object::DivisionByZero => Err(OperandError::division_by_zero()),
+        Object::IntegerTooLarge => Err(OperandError::integer_too_large()),
+        result => Ok(result),
+    }
+}
+
+pub fn unary(operator: TokenType, right: Object) -> Result<Object, OperandError> {
+    match operator {
+        TokenType::Minus => match -right {
+            Object::ArithmeticException => Ok(Object::Nil),
+            result => Ok(result),
+        },
+        TokenType::Bang => Ok(boolean(!is_truthy(&right))),
+        _ => Err(OperandError::unsupported()),
+    }
+}
+
+/// `==` for any two values. Values of different types are never equal, numbers
+/// follow IEEE 754 so NaN equals nothing, not even itself, and strings compare
+/// by content. Integers and floats are both numbers, so `1 == 1.0`. Functions,
+/// classes and instances are only equal to themselves.
+pub fn equal(left: &Object, right: &Object) -> bool {
+    match (left, right) {
+        (Object::Int(left), Object::Int(right)) => left == right,
+        (left, right) if left.is_number() && right.is_number() => left.partial_cmp(right) == Some(Ordering::Equal),
+        // Interned, so the same text is always the same symbol
+        (Object::Str(left), Object::Str(right)) => left == right,
+        (Object::Nil, Object::Nil) | (Object::True, Object::True) | (Object::False, Object::False) => true,
+        (Object::Function(left), Object::Function(right)) => Gc::ptr_eq(left, right),
+        (Object::Native(left), Object::Native(right)) => Rc::ptr_eq(left, right),
+        (Object::Class(left), Object::Class(right)) => Gc::ptr_eq(left, right),
+        (Object::Instance(left), Object::Instance(right)) => Gc::ptr_eq(left, right),
+        (Object::Closure(left), Object::Closure(right)) => Gc::ptr_eq(left, right),
+        _ => false,
+    }
+}
+
+pub fn is_truthy(object: &Object) -> bool {
+    !matches!(object, Object::Nil | Object::False)
+}
+
+/// Orders two numbers. Anything compared with NaN is false either way round.
+fn compare(left: Object, right: Object, op: fn(Ordering) -> bool) -> Object {
+    if left.is_number() && right.is_number() {
+        boolean(left.partial_cmp(&right).is_some_and(op))
+    } else {
+        Object::ArithmeticException
+    }
+}
+
+fn boolean(value: bool) -> Object {
+    if value {
+        Object::True
+    } else {
+        Object::False
+    }
+}
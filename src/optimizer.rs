@@ -0,0 +1,198 @@
+use crate::bigint::*;
+use crate::error::*;
+use crate::expr::*;
+use crate::operator;
+use crate::stmt::*;
+use crate::token::*;
+use crate::token_type::*;
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// The most bits a folded integer power may take. Anything larger is left for
+/// the script to compute, if it ever gets that far.
+const MAX_FOLDED_BITS: u64 = 64 * 1024;
+
+/// Rebuilds the tree with every operator whose operands are all constants
+/// folded into a single literal, so `1 + 2 * 3` becomes `7`. An operator that
+/// would fail is left in place to raise its error, at its own line, when the
+/// script runs. Runs before the resolver, which annotates the rebuilt tree.
+pub struct Optimizer;
+
+impl Optimizer {
+    pub fn optimize(&self, statements: &[Stmt]) -> Result<Vec<Stmt>, TikError> {
+        statements.iter().map(|stmt| stmt.accept(self)).collect()
+    }
+
+    fn expr(&self, expr: &Expr) -> Result<Box<Expr>, TikError> {
+        Ok(Box::new(expr.accept(self)?))
+    }
+
+    fn function(&self, stmt: &FunctionStmt) -> Result<FunctionStmt, TikError> {
+        Ok(FunctionStmt { name: stmt.name.clone(), params: Rc::clone(&stmt.params), body: Rc::new(self.optimize(&stmt.body)?) })
+    }
+
+    fn variable(expr: &VariableExpr) -> VariableExpr {
+        VariableExpr { name: expr.name.clone(), depth: Cell::new(expr.depth.get()) }
+    }
+
+    fn constant(expr: &Expr) -> Option<Object> {
+        match expr {
+            Expr::Literal(literal) => Some(literal.value.clone().unwrap_or(Object::Nil)),
+            _ => None,
+        }
+    }
+
+    /// Whether folding `left operator right` could take too long: an integer
+    /// power whose result may run past `MAX_FOLDED_BITS`.
+    fn too_costly(operator: TokenType, left: &Object, right: &Object) -> bool {
+        let bits = match left {
+            Object::Int(base) => BigInt::from(*base).bits(),
+            Object::Big(base) => base.bits(),
+            _ => return false,
+        };
+        match right {
+            Object::Int(exponent) if operator == TokenType::StarStar && *exponent > 0 => {
+                (*exponent as u64).saturating_mul(bits) > MAX_FOLDED_BITS
+            },
+            _ => false,
+        }
+    }
+
+    fn literal(value: Object) -> Expr {
+        Expr::Literal(LiteralExpr { value: Some(value) })
+    }
+}
+
+impl ExprVisitor<Expr> for Optimizer {
+    fn visit_assign_expr(&self, expr: &AssignExpr) -> Result<Expr, TikError> {
+        Ok(Expr::Assign(AssignExpr { name: expr.name.clone(), value: self.expr(&expr.value)?, depth: Cell::new(expr.depth.get()) }))
+    }
+
+    fn visit_binary_expr(&self, expr: &BinaryExpr) -> Result<Expr, TikError> {
+        let left = self.expr(&expr.left)?;
+        let right = self.expr(&expr.right)?;
+
+        if let (Some(l), Some(r)) = (Optimizer::constant(&left), Optimizer::constant(&right)) {
+            if !Optimizer::too_costly(expr.operator.ttype, &l, &r) {
+                if let Ok(value) = operator::binary(expr.operator.ttype, l, r) {
+                    return Ok(Optimizer::literal(value));
+                }
+            }
+        }
+        Ok(Expr::Binary(BinaryExpr { left, operator: expr.operator.clone(), right }))
+    }
+
+    fn visit_call_expr(&self, expr: &CallExpr) -> Result<Expr, TikError> {
+        let arguments = expr.arguments.iter().map(|argument| argument.accept(self)).collect::<Result<_, _>>()?;
+        Ok(Expr::Call(CallExpr { callee: self.expr(&expr.callee)?, paren: expr.paren.clone(), arguments }))
+    }
+
+    fn visit_get_expr(&self, expr: &GetExpr) -> Result<Expr, TikError> {
+        Ok(Expr::Get(GetExpr { object: self.expr(&expr.object)?, name: expr.name.clone() }))
+    }
+
+    fn visit_grouping_expr(&self, expr: &GroupingExpr) -> Result<Expr, TikError> {
+        let expression = self.expr(&expr.expression)?;
+        match Optimizer::constant(&expression) {
+            Some(value) => Ok(Optimizer::literal(value)),
+            None => Ok(Expr::Grouping(GroupingExpr { expression })),
+        }
+    }
+
+    fn visit_literal_expr(&self, expr: &LiteralExpr) -> Result<Expr, TikError> {
+        Ok(Expr::Literal(LiteralExpr { value: expr.value.clone() }))
+    }
+
+    fn visit_logical_expr(&self, expr: &LogicalExpr) -> Result<Expr, TikError> {
+        Ok(Expr::Logical(LogicalExpr { left: self.expr(&expr.left)?, operator: expr.operator.clone(), right: self.expr(&expr.right)? }))
+    }
+
+    fn visit_set_expr(&self, expr: &SetExpr) -> Result<Expr, TikError> {
+        Ok(Expr::Set(SetExpr { object: self.expr(&expr.object)?, name: expr.name.clone(), value: self.expr(&expr.value)? }))
+    }
+
+    fn visit_super_expr(&self, expr: &SuperExpr) -> Result<Expr, TikError> {
+        Ok(Expr::Super(SuperExpr { keyword: expr.keyword.clone(), method: expr.method.clone(), depth: Cell::new(expr.depth.get()) }))
+    }
+
+    fn visit_this_expr(&self, expr: &ThisExpr) -> Result<Expr, TikError> {
+        Ok(Expr::This(ThisExpr { keyword: expr.keyword.clone(), depth: Cell::new(expr.depth.get()) }))
+    }
+
+    fn visit_unary_expr(&self, expr: &UnaryExpr) -> Result<Expr, TikError> {
+        let right = self.expr(&expr.right)?;
+
+        if let Some(value) = Optimizer::constant(&right) {
+            if let Ok(value) = operator::unary(expr.operator.ttype, value) {
+                return Ok(Optimizer::literal(value));
+            }
+        }
+        Ok(Expr::Unary(UnaryExpr { operator: expr.operator.clone(), right }))
+    }
+
+    fn visit_variable_expr(&self, expr: &VariableExpr) -> Result<Expr, TikError> {
+        Ok(Expr::Variable(Optimizer::variable(expr)))
+    }
+}
+
+impl StmtVisitor<Stmt> for Optimizer {
+    fn visit_block_stmt(&self, stmt: &BlockStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::Block(BlockStmt { statements: self.optimize(&stmt.statements)? }))
+    }
+
+    fn visit_class_stmt(&self, stmt: &ClassStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::Class(ClassStmt {
+            name: stmt.name.clone(),
+            superclass: stmt.superclass.as_ref().map(Optimizer::variable),
+            methods: stmt.methods.iter().map(|method| self.function(method)).collect::<Result<_, _>>()?,
+        }))
+    }
+
+    fn visit_expression_stmt(&self, stmt: &ExpressionStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::Expression(ExpressionStmt { expression: stmt.expression.accept(self)? }))
+    }
+
+    fn visit_function_stmt(&self, stmt: &FunctionStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::Function(self.function(stmt)?))
+    }
+
+    fn visit_if_stmt(&self, stmt: &IfStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::If(IfStmt {
+            condition: stmt.condition.accept(self)?,
+            then_branch: Box::new(stmt.then_branch.accept(self)?),
+            else_branch: stmt.else_branch.as_ref().map(|branch| branch.accept(self).map(Box::new)).transpose()?,
+        }))
+    }
+
+    fn visit_print_stmt(&self, stmt: &PrintStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::Print(PrintStmt { expression: stmt.expression.accept(self)? }))
+    }
+
+    fn visit_return_stmt(&self, stmt: &ReturnStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::Return(ReturnStmt { keyword: stmt.keyword.clone(), value: stmt.value.as_ref().map(|value| value.accept(self)).transpose()? }))
+    }
+
+    fn visit_throw_stmt(&self, stmt: &ThrowStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::Throw(ThrowStmt { keyword: stmt.keyword.clone(), value: stmt.value.accept(self)? }))
+    }
+
+    fn visit_try_stmt(&self, stmt: &TryStmt) -> Result<Stmt, TikError> {
+        let catch_clause = match &stmt.catch_clause {
+            Some(catch_clause) => Some(CatchClause { name: catch_clause.name.clone(), body: self.optimize(&catch_clause.body)? }),
+            None => None,
+        };
+        let finally_body = match &stmt.finally_body {
+            Some(finally_body) => Some(Rc::new(self.optimize(finally_body)?)),
+            None => None,
+        };
+        Ok(Stmt::Try(TryStmt { body: self.optimize(&stmt.body)?, catch_clause, finally_body }))
+    }
+
+    fn visit_var_stmt(&self, stmt: &VarStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::Var(VarStmt { name: stmt.name.clone(), initializer: stmt.initializer.as_ref().map(|value| value.accept(self)).transpose()? }))
+    }
+
+    fn visit_while_stmt(&self, stmt: &WhileStmt) -> Result<Stmt, TikError> {
+        Ok(Stmt::While(WhileStmt { condition: stmt.condition.accept(self)?, body: Box::new(stmt.body.accept(self)?) }))
+    }
+}
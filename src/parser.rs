@@ -3,48 +3,312 @@ use crate::token::*;
 use crate::error::*;
 use crate::expr::*;
 use crate::stmt::*;
-use crate::Tik;
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// How deeply statements and expressions can nest in the source, which is how
+/// deeply the parser recurses.
+pub const MAX_NESTING: usize = 100;
+
+/// How deep the syntax tree can get, counting chains like `a + b + c` that the
+/// parser builds without recursing. Every pass after parsing recurses on the
+/// tree, so this bounds the native stack they use.
+const MAX_DEPTH: usize = 1000;
 
-#[derive(Clone)]
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    errors: Vec<TikError>,
+    // Statements and expressions being parsed around the current one
+    depth: usize,
+    // Nodes on the longest path down the expression parsed last
+    height: usize,
+    // Set once nesting went past a limit and the rest of the tokens were skipped
+    too_deep: bool,
 }
 
-#[allow(dead_code)]
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, current: 0 }
+        Parser { tokens, current: 0, errors: Vec::new(), depth: 0, height: 0, too_deep: false }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, TikError> {
+    /// Parses every declaration in the token stream. A declaration with a syntax
+    /// error is skipped after recovering at the next statement boundary, so the
+    /// returned statements cover the valid parts and the errors cover the rest.
+    pub fn parse(&mut self) -> (Vec<Stmt>, Vec<TikError>) {
         let mut statements = Vec::new();
+
         while !self.is_at_end() {
-            statements.push(self.statement()?)
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
-        Ok(statements)
-        //self.expression()
-        /*
-        match self.expression() {
-            Ok(expr) => Some(expr),
-            Err(_) => None,
+        (statements, std::mem::take(&mut self.errors))
+    }
+
+    fn declaration(&mut self) -> Option<Stmt> {
+        match self.try_declaration() {
+            Ok(statement) => Some(statement),
+            Err(e) => {
+                // After nesting too deeply, every construct still open would
+                // report its missing end as well
+                if !self.too_deep || e.kind() == ErrorKind::Parse(ParseError::TooDeeplyNested) {
+                    self.errors.push(e);
+                }
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn try_declaration(&mut self) -> Result<Stmt, TikError> {
+        if self.is_match(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+        if self.is_match(&[TokenType::Fun]) {
+            return Ok(Stmt::Function(self.function("function")?));
+        }
+        if self.is_match(&[TokenType::Var]) {
+            return self.var_declaration();
+        }
+        self.statement()
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, TikError> {
+        let name = self.consume(TokenType::Identifier, "Expect class name".to_string())?;
+
+        let superclass = if self.is_match(&[TokenType::Less]) {
+            let superclass = self.consume(TokenType::Identifier, "Expect superclass name".to_string())?;
+            // Reported without unwinding, since the class body after it is still well formed
+            if superclass.lexeme == name.lexeme {
+                self.errors.push(Parser::error(&superclass, ParseError::InheritFromSelf, "A class can't inherit from itself".to_string()));
+            }
+            Some(VariableExpr { name: superclass, depth: Cell::new(None) })
+        } else {
+            None
+        };
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body".to_string())?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
         }
-        */
+
+        self.consume(TokenType::RightBrace, "Expect '}' after class body".to_string())?;
+        Ok(Stmt::Class(ClassStmt { name, superclass, methods }))
+    }
+
+    fn function(&mut self, kind: &str) -> Result<FunctionStmt, TikError> {
+        self.nested(|parser| parser.function_declaration(kind))
+    }
+
+    fn function_declaration(&mut self, kind: &str) -> Result<FunctionStmt, TikError> {
+        let name = self.consume(TokenType::Identifier, format!("Expect {kind} name"))?;
+        self.consume(TokenType::LeftParen, format!("Expect '(' after {kind} name"))?;
+
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    return Err(Parser::error(self.peek(), ParseError::TooManyParameters, "Can't have more than 255 parameters".to_string()));
+                }
+                params.push(self.consume(TokenType::Identifier, "Expect parameter name".to_string())?);
+
+                if !self.is_match(&[TokenType::Coma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters".to_string())?;
+
+        self.consume(TokenType::LeftBrace, format!("Expect '{{' before {kind} body"))?;
+        let body = self.block()?;
+
+        Ok(FunctionStmt { name, params: Rc::new(params), body: Rc::new(body) })
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, TikError> {
+        let name = self.consume(TokenType::Identifier, "Expect variable name".to_string())?;
+
+        let initializer = if self.is_match(&[TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(TokenType::SemiColon, "Expect ';' after variable declaration".to_string())?;
+        Ok(Stmt::Var(VarStmt { name, initializer }))
     }
 
     fn statement(&mut self) -> Result<Stmt, TikError> {
+        self.nested(Parser::any_statement)
+    }
+
+    fn any_statement(&mut self) -> Result<Stmt, TikError> {
+        if self.is_match(&[TokenType::For]) {
+            return self.for_statement();
+        }
+        if self.is_match(&[TokenType::If]) {
+            return self.if_statement();
+        }
         if self.is_match(&[TokenType::Print]) {
             return self.print_statement();
         }
+        if self.is_match(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+        if self.is_match(&[TokenType::Throw]) {
+            return self.throw_statement();
+        }
+        if self.is_match(&[TokenType::Try]) {
+            return self.try_statement();
+        }
+        if self.is_match(&[TokenType::While]) {
+            return self.while_statement();
+        }
+        if self.is_match(&[TokenType::LeftBrace]) {
+            return Ok(Stmt::Block(BlockStmt { statements: self.block()? }));
+        }
         self.expression_statement()
     }
 
+    fn for_statement(&mut self) -> Result<Stmt, TikError> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'".to_string())?;
+
+        let initializer = if self.is_match(&[TokenType::SemiColon]) {
+            None
+        } else if self.is_match(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(TokenType::SemiColon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::SemiColon, "Expect ';' after loop condition".to_string())?;
+
+        let increment = if self.check(TokenType::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses".to_string())?;
+
+        let mut body = self.statement()?;
+
+        // Desugar into a while loop wrapped in blocks for the increment and initializer
+        if let Some(increment) = increment {
+            body = Stmt::Block(BlockStmt {
+                statements: vec![body, Stmt::Expression(ExpressionStmt { expression: increment })],
+            });
+        }
+
+        let condition = condition.unwrap_or(Expr::Literal(LiteralExpr { value: Some(Object::True) }));
+        body = Stmt::While(WhileStmt { condition, body: Box::new(body) });
+
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(BlockStmt { statements: vec![initializer, body] });
+        }
+
+        Ok(body)
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, TikError> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'".to_string())?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition".to_string())?;
+
+        let then_branch = Box::new(self.statement()?);
+        // The else binds to the nearest if since the inner statement() call claims it first
+        let else_branch = if self.is_match(&[TokenType::Else]) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If(IfStmt { condition, then_branch, else_branch }))
+    }
+
     fn print_statement(&mut self) -> Result<Stmt, TikError> {
         let value = self.expression()?;
         self.consume(TokenType::SemiColon, "Expect ';' after value".to_string())?;
         Ok(Stmt::Print(PrintStmt { expression: value } ))
     }
 
+    fn throw_statement(&mut self) -> Result<Stmt, TikError> {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(TokenType::SemiColon, "Expect ';' after thrown value".to_string())?;
+
+        Ok(Stmt::Throw(ThrowStmt { keyword, value }))
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, TikError> {
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'".to_string())?;
+        let body = self.block()?;
+
+        let catch_clause = if self.is_match(&[TokenType::Catch]) {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'".to_string())?;
+            let name = self.consume(TokenType::Identifier, "Expect error name".to_string())?;
+            self.consume(TokenType::RightParen, "Expect ')' after error name".to_string())?;
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body".to_string())?;
+            Some(CatchClause { name, body: self.block()? })
+        } else {
+            None
+        };
+
+        let finally_body = if self.is_match(&[TokenType::Finally]) {
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'".to_string())?;
+            Some(Rc::new(self.block()?))
+        } else {
+            None
+        };
+
+        if catch_clause.is_none() && finally_body.is_none() {
+            return Err(Parser::error(self.peek(), ParseError::ExpectedToken, "Expect 'catch' or 'finally' after try block".to_string()));
+        }
+
+        Ok(Stmt::Try(TryStmt { body, catch_clause, finally_body }))
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, TikError> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'".to_string())?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition".to_string())?;
+        let body = Box::new(self.statement()?);
+
+        Ok(Stmt::While(WhileStmt { condition, body }))
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, TikError> {
+        let mut statements = Vec::new();
+
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block".to_string())?;
+        Ok(statements)
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, TikError> {
+        let keyword = self.previous().clone();
+        let value = if self.check(TokenType::SemiColon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+
+        self.consume(TokenType::SemiColon, "Expect ';' after return value".to_string())?;
+        Ok(Stmt::Return(ReturnStmt { keyword, value }))
+    }
+
     fn expression_statement(&mut self) -> Result<Stmt, TikError> {
         let expr = self.expression()?;
         self.consume(TokenType::SemiColon, "Expect ';' after value".to_string())?;
@@ -52,15 +316,76 @@ impl Parser {
     }
 
     fn expression(&mut self) -> Result<Expr, TikError> {
-        self.equality()
+        self.nested(Parser::assignment)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, TikError> {
+        let expr = self.or()?;
+
+        if self.is_match(&[TokenType::Equal]) {
+            let equals = self.previous().clone();
+            let target = self.height;
+            let value = self.expression()?;
+            self.node(target.max(self.height))?;
+
+            match expr {
+                Expr::Variable(expr) => {
+                    return Ok(Expr::Assign(AssignExpr { name: expr.name, value: Box::new(value), depth: Cell::new(None) }));
+                },
+                Expr::Get(expr) => {
+                    return Ok(Expr::Set(SetExpr { object: expr.object, name: expr.name, value: Box::new(value) }));
+                },
+                _ => {},
+            }
+
+            return Err(Parser::error(&equals, ParseError::InvalidAssignmentTarget, "Invalid assignment target".to_string()));
+        }
+
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, TikError> {
+        let mut expr = self.and()?;
+
+        while self.is_match(&[TokenType::Or]) {
+            let operator = self.previous().clone();
+            let left = self.height;
+            let right = self.and()?;
+            self.node(left.max(self.height))?;
+            expr = Expr::Logical(LogicalExpr {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, TikError> {
+        let mut expr = self.equality()?;
+
+        while self.is_match(&[TokenType::And]) {
+            let operator = self.previous().clone();
+            let left = self.height;
+            let right = self.equality()?;
+            self.node(left.max(self.height))?;
+            expr = Expr::Logical(LogicalExpr {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+        Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expr, TikError> {
         let mut expr = self.comparision()?;
 
         while self.is_match(&[TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
+            let left = self.height;
             let right = self.comparision()?;
+            self.node(left.max(self.height))?;
             expr = Expr::Binary(BinaryExpr {
                 left: Box::new(expr),
                 operator,
@@ -74,8 +399,10 @@ impl Parser {
         let mut expr = self.term()?;
 
         while self.is_match(&[TokenType::Greater, TokenType:: GreaterEqual, TokenType::Less, TokenType::LessEqual]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
+            let left = self.height;
             let right = self.term()?;
+            self.node(left.max(self.height))?;
             expr = Expr::Binary(BinaryExpr {
                 left: Box::new(expr),
                 operator,
@@ -89,8 +416,10 @@ impl Parser {
         let mut expr = self.factor()?;
 
         while self.is_match(&[TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
+            let left = self.height;
             let right = self.factor()?;
+            self.node(left.max(self.height))?;
             expr = Expr::Binary(BinaryExpr {
                 left: Box::new(expr),
                 operator,
@@ -103,9 +432,11 @@ impl Parser {
     fn factor(&mut self) -> Result<Expr, TikError> {
         let mut expr = self.unary()?;
 
-        while self.is_match(&[TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous();
+        while self.is_match(&[TokenType::Slash, TokenType::Star, TokenType::Percent, TokenType::TildeSlash]) {
+            let operator = self.previous().clone();
+            let left = self.height;
             let right = self.unary()?;
+            self.node(left.max(self.height))?;
             expr = Expr::Binary(BinaryExpr {
                 left: Box::new(expr),
                 operator,
@@ -117,15 +448,74 @@ impl Parser {
 
     fn unary(&mut self) -> Result<Expr, TikError> {
         if self.is_match(&[TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous();
-            let right = self.unary()?;
+            let operator = self.previous().clone();
+            let right = self.nested(Parser::unary)?;
+            self.node(self.height)?;
             return Ok(Expr::Unary(UnaryExpr { operator, right: Box::new(right) }));
         }
 
-        Ok(self.primary()?)
+        self.power()
+    }
+
+    /// `**` binds tighter than a unary operator on its left, so `-2 ** 2` is
+    /// `-(2 ** 2)`, and groups to the right, so `2 ** 3 ** 2` is `2 ** 9`. The
+    /// exponent may have its own sign, as in `2 ** -1`.
+    fn power(&mut self) -> Result<Expr, TikError> {
+        let expr = self.call()?;
+
+        if self.is_match(&[TokenType::StarStar]) {
+            let operator = self.previous().clone();
+            let left = self.height;
+            let right = self.nested(Parser::unary)?;
+            self.node(left.max(self.height))?;
+            return Ok(Expr::Binary(BinaryExpr { left: Box::new(expr), operator, right: Box::new(right) }));
+        }
+        Ok(expr)
+    }
+
+    fn call(&mut self) -> Result<Expr, TikError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.is_match(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.is_match(&[TokenType::Dot]) {
+                let name = self.consume(TokenType::Identifier, "Expect property name after '.'".to_string())?;
+                self.node(self.height)?;
+                expr = Expr::Get(GetExpr { object: Box::new(expr), name });
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, TikError> {
+        let mut height = self.height;
+        let mut arguments = Vec::new();
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    return Err(Parser::error(self.peek(), ParseError::TooManyArguments, "Can't have more than 255 arguments".to_string()));
+                }
+                arguments.push(self.expression()?);
+                height = height.max(self.height);
+
+                if !self.is_match(&[TokenType::Coma]) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments".to_string())?;
+        self.node(height)?;
+        Ok(Expr::Call(CallExpr { callee: Box::new(callee), paren, arguments }))
     }
 
     fn primary(&mut self) -> Result<Expr, TikError> {
+        self.height = 1;
         if self.is_match(&[TokenType::False]) {
             return Ok(Expr::Literal(LiteralExpr {value: Some(Object::False)}));
         }
@@ -137,24 +527,70 @@ impl Parser {
         }
 
         if self.is_match(&[TokenType::Number, TokenType::String]) {
-            return Ok(Expr::Literal(LiteralExpr {value: self.previous().literal}))
+            return Ok(Expr::Literal(LiteralExpr {value: self.previous().literal.clone()}))
+        }
+
+        if self.is_match(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'".to_string())?;
+            let method = self.consume(TokenType::Identifier, "Expect superclass method name".to_string())?;
+            return Ok(Expr::Super(SuperExpr { keyword, method, depth: Cell::new(None) }));
+        }
+
+        if self.is_match(&[TokenType::This]) {
+            return Ok(Expr::This(ThisExpr { keyword: self.previous().clone(), depth: Cell::new(None) }));
+        }
+
+        if self.is_match(&[TokenType::Identifier]) {
+            return Ok(Expr::Variable(VariableExpr { name: self.previous().clone(), depth: Cell::new(None) }));
         }
 
         if self.is_match(&[TokenType::LeftParen]) {
             let expr = self.expression()?;
             let _ = self.consume(TokenType::RightParen, "Expect ')' after expression".to_string())?;
+            self.node(self.height)?;
             return Ok(Expr::Grouping(GroupingExpr {expression: Box::new(expr) }));
         }
 
-        Err(Parser::error(self.peek(), "Expect Expression".to_string()))
+        Err(Parser::error(self.peek(), ParseError::ExpectedExpression, "Expect Expression".to_string()))
         //Err(TikError::error(self.peek().line, "Expect Expression".to_string()))
     }
 
+    /// Parses a declaration, statement or expression nested inside the current one.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Parser) -> Result<T, TikError>) -> Result<T, TikError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.too_deeply_nested());
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Notes that an expression node was built over subexpressions at most
+    /// `children` nodes high, as long as that keeps the tree within `MAX_DEPTH`.
+    fn node(&mut self, children: usize) -> Result<(), TikError> {
+        self.height = children + 1;
+        if self.depth + self.height > MAX_DEPTH {
+            return Err(self.too_deeply_nested());
+        }
+        Ok(())
+    }
+
+    /// Gives up on the rest of the tokens, since every construct still open
+    /// would fail on its own at the next level out.
+    fn too_deeply_nested(&mut self) -> TikError {
+        let e = Parser::error(self.peek(), ParseError::TooDeeplyNested, "Too deeply nested".to_string());
+        self.current = self.tokens.len() - 1;
+        self.too_deep = true;
+        e
+    }
+
     fn consume(&mut self, ttype: TokenType, message: String) -> Result<Token, TikError> {
         if self.check(ttype) {
-            Ok(self.advance())
+            Ok(self.advance().clone())
         } else {
-            Err(Parser::error(self.peek(), message))
+            Err(Parser::error(self.peek(), ParseError::ExpectedToken, message))
             //Err(TikError::error(p.line, message))
         }
     }
@@ -175,6 +611,8 @@ impl Parser {
                 TokenType::If |
                 TokenType::While |
                 TokenType::Print |
+                TokenType::Try |
+                TokenType::Throw |
                 TokenType::Return=> { return },
                 _ => {},
             }
@@ -200,7 +638,7 @@ impl Parser {
         }
     }
 
-    fn advance(&mut self) -> Token {
+    fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1; 
         }
@@ -211,16 +649,16 @@ impl Parser {
         self.peek().ttype == TokenType::Eof
     }
 
-    fn peek(&self) -> Token {
-        self.tokens.get(self.current).unwrap().clone()
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
     }
 
-    fn previous(&self) -> Token {
-        self.tokens.get(self.current - 1).unwrap().clone()
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current - 1]
     }
 
-    fn error(token: Token, message: String) -> TikError {
-        TikError::error_parser(token, message)
+    fn error(token: &Token, kind: ParseError, message: String) -> TikError {
+        TikError::error_parser(kind, token.clone(), message)
     }
 
 }
@@ -0,0 +1,329 @@
+use crate::error::*;
+use crate::expr::*;
+use crate::stmt::*;
+use crate::token::*;
+use crate::symbol::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Walks the AST once before it runs and records, on every variable reference,
+/// how many scopes separate it from the scope that declares it.
+pub struct Resolver {
+    scopes: RefCell<Vec<HashMap<Symbol, bool>>>,
+    current_function: Cell<FunctionType>,
+    current_class: Cell<ClassType>,
+    errors: RefCell<Vec<TikError>>,
+}
+
+impl Default for Resolver {
+    fn default() -> Resolver {
+        Resolver::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            scopes: RefCell::new(Vec::new()),
+            current_function: Cell::new(FunctionType::None),
+            current_class: Cell::new(ClassType::None),
+            errors: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn resolve(&self, statements: &[Stmt]) -> Result<(), Vec<TikError>> {
+        if let Err(e) = self.resolve_statements(statements) {
+            self.errors.borrow_mut().push(e);
+        }
+
+        let errors = self.errors.take();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn resolve_statements(&self, statements: &[Stmt]) -> Result<(), TikError> {
+        for statement in statements {
+            statement.accept(self)?;
+        }
+        Ok(())
+    }
+
+    fn resolve_expr(&self, expr: &Expr) -> Result<(), TikError> {
+        expr.accept(self)
+    }
+
+    fn resolve_function(&self, function: &FunctionStmt, ftype: FunctionType) -> Result<(), TikError> {
+        let enclosing_function = self.current_function.replace(ftype);
+
+        self.begin_scope();
+        for param in function.params.iter() {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&function.body)?;
+        self.end_scope();
+
+        self.current_function.set(enclosing_function);
+        Ok(())
+    }
+
+    fn resolve_local(&self, name: &Token, depth: &Cell<Option<usize>>) {
+        // Anything not found in a local scope is left unresolved and treated as a global
+        for (distance, scope) in self.scopes.borrow().iter().rev().enumerate() {
+            if scope.contains_key(&name.lexeme) {
+                depth.set(Some(distance));
+                return;
+            }
+        }
+    }
+
+    fn begin_scope(&self) {
+        self.scopes.borrow_mut().push(HashMap::new());
+    }
+
+    fn end_scope(&self) {
+        self.scopes.borrow_mut().pop();
+    }
+
+    fn declare(&self, name: &Token) {
+        if let Some(scope) = self.scopes.borrow_mut().last_mut() {
+            if scope.contains_key(&name.lexeme) {
+                let e = TikError::resolve_error(ResolveError::DuplicateVariable, name.clone(), "Already a variable with this name in this scope".to_string())
+                    .with_help(format!("drop the `var` to assign to the existing '{}'", name.lexeme));
+                self.errors.borrow_mut().push(e);
+            }
+            scope.insert(name.lexeme.clone(), false);
+        }
+    }
+
+    fn define(&self, name: &Token) {
+        if let Some(scope) = self.scopes.borrow_mut().last_mut() {
+            scope.insert(name.lexeme.clone(), true);
+        }
+    }
+
+    /// Static errors don't stop the pass so that every one of them gets reported.
+    fn error(&self, token: &Token, kind: ResolveError, message: &str) {
+        let e = TikError::resolve_error(kind, token.clone(), message.to_string());
+        self.errors.borrow_mut().push(e);
+    }
+}
+
+impl StmtVisitor<()> for Resolver {
+    fn visit_block_stmt(&self, stmt: &BlockStmt) -> Result<(), TikError> {
+        self.begin_scope();
+        self.resolve_statements(&stmt.statements)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_class_stmt(&self, stmt: &ClassStmt) -> Result<(), TikError> {
+        let enclosing_class = self.current_class.replace(ClassType::Class);
+
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+
+        if let Some(superclass) = &stmt.superclass {
+            self.current_class.set(ClassType::Subclass);
+            superclass.accept(self)?;
+
+            self.begin_scope();
+            self.scopes.borrow_mut().last_mut().unwrap().insert(Symbol::intern("super"), true);
+        }
+
+        self.begin_scope();
+        self.scopes.borrow_mut().last_mut().unwrap().insert(Symbol::intern("this"), true);
+
+        for method in &stmt.methods {
+            let ftype = if method.name.lexeme == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, ftype)?;
+        }
+
+        self.end_scope();
+
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.current_class.set(enclosing_class);
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&self, stmt: &ExpressionStmt) -> Result<(), TikError> {
+        self.resolve_expr(&stmt.expression)
+    }
+
+    fn visit_function_stmt(&self, stmt: &FunctionStmt) -> Result<(), TikError> {
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+        self.resolve_function(stmt, FunctionType::Function)
+    }
+
+    fn visit_if_stmt(&self, stmt: &IfStmt) -> Result<(), TikError> {
+        self.resolve_expr(&stmt.condition)?;
+        stmt.then_branch.accept(self)?;
+        if let Some(else_branch) = &stmt.else_branch {
+            else_branch.accept(self)?;
+        }
+        Ok(())
+    }
+
+    fn visit_print_stmt(&self, stmt: &PrintStmt) -> Result<(), TikError> {
+        self.resolve_expr(&stmt.expression)
+    }
+
+    fn visit_return_stmt(&self, stmt: &ReturnStmt) -> Result<(), TikError> {
+        if self.current_function.get() == FunctionType::None {
+            self.error(&stmt.keyword, ResolveError::TopLevelReturn, "Can't return from top-level code");
+        }
+
+        if let Some(value) = &stmt.value {
+            if self.current_function.get() == FunctionType::Initializer {
+                self.error(&stmt.keyword, ResolveError::ReturnFromInitializer, "Can't return a value from an initializer");
+            }
+            self.resolve_expr(value)?;
+        }
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&self, stmt: &ThrowStmt) -> Result<(), TikError> {
+        self.resolve_expr(&stmt.value)
+    }
+
+    fn visit_try_stmt(&self, stmt: &TryStmt) -> Result<(), TikError> {
+        self.begin_scope();
+        self.resolve_statements(&stmt.body)?;
+        self.end_scope();
+
+        // The error name shares a scope with the catch body, like parameters do with a function body
+        if let Some(catch_clause) = &stmt.catch_clause {
+            self.begin_scope();
+            self.declare(&catch_clause.name);
+            self.define(&catch_clause.name);
+            self.resolve_statements(&catch_clause.body)?;
+            self.end_scope();
+        }
+
+        if let Some(finally_body) = &stmt.finally_body {
+            self.begin_scope();
+            self.resolve_statements(finally_body)?;
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_var_stmt(&self, stmt: &VarStmt) -> Result<(), TikError> {
+        self.declare(&stmt.name);
+        if let Some(initializer) = &stmt.initializer {
+            self.resolve_expr(initializer)?;
+        }
+        self.define(&stmt.name);
+        Ok(())
+    }
+
+    fn visit_while_stmt(&self, stmt: &WhileStmt) -> Result<(), TikError> {
+        self.resolve_expr(&stmt.condition)?;
+        stmt.body.accept(self)
+    }
+}
+
+impl ExprVisitor<()> for Resolver {
+    fn visit_assign_expr(&self, expr: &AssignExpr) -> Result<(), TikError> {
+        self.resolve_expr(&expr.value)?;
+        self.resolve_local(&expr.name, &expr.depth);
+        Ok(())
+    }
+
+    fn visit_binary_expr(&self, expr: &BinaryExpr) -> Result<(), TikError> {
+        self.resolve_expr(&expr.left)?;
+        self.resolve_expr(&expr.right)
+    }
+
+    fn visit_call_expr(&self, expr: &CallExpr) -> Result<(), TikError> {
+        self.resolve_expr(&expr.callee)?;
+        for argument in &expr.arguments {
+            self.resolve_expr(argument)?;
+        }
+        Ok(())
+    }
+
+    fn visit_get_expr(&self, expr: &GetExpr) -> Result<(), TikError> {
+        self.resolve_expr(&expr.object)
+    }
+
+    fn visit_grouping_expr(&self, expr: &GroupingExpr) -> Result<(), TikError> {
+        self.resolve_expr(&expr.expression)
+    }
+
+    fn visit_literal_expr(&self, _expr: &LiteralExpr) -> Result<(), TikError> {
+        Ok(())
+    }
+
+    fn visit_logical_expr(&self, expr: &LogicalExpr) -> Result<(), TikError> {
+        self.resolve_expr(&expr.left)?;
+        self.resolve_expr(&expr.right)
+    }
+
+    fn visit_set_expr(&self, expr: &SetExpr) -> Result<(), TikError> {
+        self.resolve_expr(&expr.value)?;
+        self.resolve_expr(&expr.object)
+    }
+
+    fn visit_super_expr(&self, expr: &SuperExpr) -> Result<(), TikError> {
+        match self.current_class.get() {
+            ClassType::None => self.error(&expr.keyword, ResolveError::SuperOutsideClass, "Can't use 'super' outside of a class"),
+            ClassType::Class => self.error(&expr.keyword, ResolveError::SuperWithoutSuperclass, "Can't use 'super' in a class with no superclass"),
+            ClassType::Subclass => {},
+        }
+
+        self.resolve_local(&expr.keyword, &expr.depth);
+        Ok(())
+    }
+
+    fn visit_this_expr(&self, expr: &ThisExpr) -> Result<(), TikError> {
+        if self.current_class.get() == ClassType::None {
+            self.error(&expr.keyword, ResolveError::ThisOutsideClass, "Can't use 'this' outside of a class");
+            return Ok(());
+        }
+
+        self.resolve_local(&expr.keyword, &expr.depth);
+        Ok(())
+    }
+
+    fn visit_unary_expr(&self, expr: &UnaryExpr) -> Result<(), TikError> {
+        self.resolve_expr(&expr.right)
+    }
+
+    fn visit_variable_expr(&self, expr: &VariableExpr) -> Result<(), TikError> {
+        if let Some(scope) = self.scopes.borrow().last() {
+            if scope.get(&expr.name.lexeme) == Some(&false) {
+                self.error(&expr.name, ResolveError::ReadInOwnInitializer, "Can't read local variable in its own initializer");
+            }
+        }
+
+        self.resolve_local(&expr.name, &expr.depth);
+        Ok(())
+    }
+}
@@ -1,47 +1,68 @@
 use crate::token::*;
 use crate::token_type::*;
 use crate::error::*;
+use crate::symbol::*;
+use crate::bigint::*;
 
-pub struct Scanner {
-    source: Vec<char>,
+/// Walks the source in place. `start` and `current` are byte offsets, so a
+/// lexeme is a slice of the source and only gets copied when it's first interned.
+pub struct Scanner<'a> {
+    source: &'a str,
     tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: usize,
+    start_line: usize,
+    // 1-based columns, counted in characters, of the next character and of the lexeme's start
+    column: usize,
+    start_column: usize,
 }
 
-impl Scanner {
-    pub fn new(source: String) -> Scanner {
+impl<'a> Scanner<'a> {
+    pub fn new(source: &'a str) -> Scanner<'a> {
         Scanner {
-            source: source.chars().collect(),
+            source,
             tokens: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            start_line: 1,
+            column: 1,
+            start_column: 1,
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Result<&Vec<Token>, TikError> {
-        let mut had_error: Option<TikError> = None;
-        
+    /// Scans the whole source. A character that can't start a token is reported
+    /// and skipped, so the tokens around it can still be parsed for more errors.
+    pub fn scan_tokens(mut self) -> (Vec<Token>, Vec<TikError>) {
+        let mut errors = Vec::new();
+
         while !self.is_at_end() {
             self.start = self.current;
-            match self.scan_token() {
-                Ok(_) => {},
-                Err(e) => {
-                    e.report("Unexpected character".to_string());
-                    had_error = Some(e);
-                }
+            self.start_line = self.line;
+            self.start_column = self.column;
+            if let Err(e) = self.scan_token() {
+                errors.push(e);
             }
         }
 
-        self.tokens.push(Token::eof(self.line));
-
-        if let Some(e) = had_error {
-            Err(e)
+        // End of input is reported just past the last token rather than on trailing blank lines
+        let eof = if let Some(last) = self.tokens.last() {
+            let span = Span {
+                start: last.span.end,
+                end: last.span.end,
+                column: last.span.end_column,
+                end_column: last.span.end_column,
+            };
+            Token::eof(last.line, span)
         } else {
-            Ok(&self.tokens)
-        }
+            self.start = self.current;
+            self.start_column = self.column;
+            Token::eof(self.line, self.span())
+        };
+        self.tokens.push(eof);
+
+        (self.tokens, errors)
     }
 
     fn is_at_end(&self) -> bool {
@@ -61,7 +82,20 @@ impl Scanner {
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::SemiColon),
-            '*' => self.add_token(TokenType::Star),
+            '*' => {
+                let tok = if self.is_match('*') {
+                    TokenType::StarStar
+                } else {
+                    TokenType::Star
+                };
+                self.add_token(tok);
+            },
+            '%' => self.add_token(TokenType::Percent),
+            // Floor division; "//" already starts a comment
+            '~' if self.peek() == Some('/') => {
+                self.advance();
+                self.add_token(TokenType::TildeSlash);
+            },
             '!' => {
                 let tok = if self.is_match('=') {
                     TokenType::BangEqual
@@ -107,13 +141,13 @@ impl Scanner {
                     }
                 } else if self.is_match('*'){
                     // Block Comment
-                    while let Some(_) = self.peek() {
+                    while self.peek().is_some() {
                         if self.is_match('*') {
                             if self.is_match('/') {
                                 break;
                             } 
                         } else if self.is_match('\n') {
-                            self.line += 1;
+                            self.newline();
                         }
                         else {
                             self.advance();
@@ -125,7 +159,7 @@ impl Scanner {
                 }
             },
             ' ' | '\r' | '\t' => {},
-            '\n' => { self.line += 1; },
+            '\n' => self.newline(),
             '"' => {
                 self.string()?;
             },
@@ -137,7 +171,7 @@ impl Scanner {
                 },
             
             _   => {
-                return Err(TikError::error(self.line, "Unexpected charater".to_string()));
+                return Err(TikError::lex_error(LexError::UnexpectedCharacter, self.start_line, self.span(), format!("Unexpected character '{c}'")));
             }
         }
 
@@ -145,9 +179,25 @@ impl Scanner {
     }
 
     fn advance(&mut self) -> char {
-        let result = self.source.get(self.current).unwrap();
-        self.current += 1;
-        *result
+        let result = self.peek().unwrap();
+        self.current += result.len_utf8();
+        self.column += 1;
+        result
+    }
+
+    /// Called after consuming a '\n' so columns restart on the new line.
+    fn newline(&mut self) {
+        self.line += 1;
+        self.column = 1;
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            end: self.current,
+            column: self.start_column,
+            end_column: self.column,
+        }
     }
 
     fn add_token(&mut self, ttype: TokenType) {
@@ -155,31 +205,29 @@ impl Scanner {
     }
 
     fn add_token_object(&mut self, ttype: TokenType, literal: Option<Object>) {
-        let lexeme: String = self.source[self.start .. self.current].iter().collect();
-        self.tokens.push(Token::new(ttype, lexeme, literal, self.line));
+        let lexeme = Symbol::intern(&self.source[self.start .. self.current]);
+        self.tokens.push(Token::new(ttype, lexeme, literal, self.start_line, self.span()));
     }
 
     fn string(&mut self) -> Result<(), TikError> {
         while let Some(ch) = self.peek() {
-            match ch {
-                '"' => {
-                    break;
-                },
-                '\n' => {
-                    self.line += 1;
-                },
-                _ => {}
+            if ch == '"' {
+                break;
+            }
+            if self.advance() == '\n' {
+                self.newline();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            return Err(TikError::error(self.line, "Unterminated string".to_string()));
+            return Err(TikError::lex_error(LexError::UnterminatedString, self.start_line, self.span(), "Unterminated string".to_string())
+                .with_help("close the string with a '\"'".to_string()));
         }
 
         self.advance();
 
-        let value = self.source[self.start + 1 .. self.current -1].iter().collect();
+        // The quotes are one byte each
+        let value = Symbol::intern(&self.source[self.start + 1 .. self.current - 1]);
         self.add_token_object(TokenType::String, Some(Object::Str(value)));
         Ok(())
     }
@@ -201,7 +249,9 @@ impl Scanner {
     }
 
     fn peek_next(&self) -> Option<char> {
-        self.source.get(self.current + 1).copied()
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next()
     }
 
     fn number(&mut self) -> Result<(), TikError> {
@@ -209,21 +259,21 @@ impl Scanner {
             self.advance();
         }
 
-        if let Some(ch) = self.peek() {
-            if ch == '.' {
-                if Scanner::is_digit(self.peek_next()) {
-                    self.advance();
+        if self.peek() == Some('.') && Scanner::is_digit(self.peek_next()) {
+            self.advance();
 
-                    while Scanner::is_digit(self.peek()) {
-                        self.advance();
-                    }
-                }
+            while Scanner::is_digit(self.peek()) {
+                self.advance();
             }
+
+            let num: f64 = self.source[self.start .. self.current].parse().unwrap();
+            self.add_token_object(TokenType::Number, Some(Object::Num(num)));
+            return Ok(());
         }
 
-        let value: String = self.source[self.start .. self.current].iter().collect();
-        let num: f64 = value.parse().unwrap();
-        self.add_token_object(TokenType::Number, Some(Object::Num(num)));
+        // Without a '.' it's an integer, exact however many digits it has
+        let num = Object::integer(BigInt::from_decimal(&self.source[self.start .. self.current]));
+        self.add_token_object(TokenType::Number, Some(num));
         Ok(())
     }
 
@@ -232,8 +282,7 @@ impl Scanner {
             self.advance();
         }
 
-        let text: String = self.source[self.start .. self.current].iter().collect();
-        if let Some(ttype) = Scanner::keyword(text.as_str()) {
+        if let Some(ttype) = Scanner::keyword(&self.source[self.start .. self.current]) {
             self.add_token(ttype);
         } else {
             self.add_token(TokenType::Identifier);
@@ -241,18 +290,20 @@ impl Scanner {
     }
 
     fn is_match(&mut self, expected: char) -> bool {
-        match self.source.get(self.current) {
-            Some(ch) if *ch == expected => {
-                self.current += 1;
-                true
-            }
-
-            _ => false,
+        if self.peek() == Some(expected) {
+            self.advance();
+            true
+        } else {
+            false
         }
     }
 
     fn peek(&self) -> Option<char> {
-        self.source.get(self.current).copied()
+        match self.source.as_bytes().get(self.current) {
+            Some(&byte) if byte.is_ascii() => Some(byte as char),
+            Some(_) => self.source[self.current..].chars().next(),
+            None => None,
+        }
     }
 
     fn keyword(check: &str) -> Option<TokenType> {
@@ -273,6 +324,10 @@ impl Scanner {
             "var"    => Some(TokenType::Var),
             "while"  => Some(TokenType::While),
             "for"    => Some(TokenType::For),
+            "try"    => Some(TokenType::Try),
+            "catch"  => Some(TokenType::Catch),
+            "finally" => Some(TokenType::Finally),
+            "throw"  => Some(TokenType::Throw),
             _ => None,
         }
     }
@@ -1,9 +1,25 @@
-use tik::define_ast;
 use crate::error::*;
 use crate::expr::*;
+use crate::token::*;
+use std::rc::Rc;
 
 define_ast!(
     Stmt/StmtVisitor,
+    Block/BlockStmt/visit_block_stmt { statements: Vec<Stmt> }
+    Class/ClassStmt/visit_class_stmt { name: Token, superclass: Option<VariableExpr>, methods: Vec<FunctionStmt> }
     Expression/ExpressionStmt/visit_expression_stmt { expression: Expr }
+    Function/FunctionStmt/visit_function_stmt { name: Token, params: Rc<Vec<Token>>, body: Rc<Vec<Stmt>> }
+    If/IfStmt/visit_if_stmt { condition: Expr, then_branch: Box<Stmt>, else_branch: Option<Box<Stmt>> }
     Print/PrintStmt/visit_print_stmt { expression: Expr }
+    Return/ReturnStmt/visit_return_stmt { keyword: Token, value: Option<Expr> }
+    Throw/ThrowStmt/visit_throw_stmt { keyword: Token, value: Expr }
+    Try/TryStmt/visit_try_stmt { body: Vec<Stmt>, catch_clause: Option<CatchClause>, finally_body: Option<Rc<Vec<Stmt>>> }
+    Var/VarStmt/visit_var_stmt { name: Token, initializer: Option<Expr> }
+    While/WhileStmt/visit_while_stmt { condition: Expr, body: Box<Stmt> }
     );
+
+/// `catch (name) { body }`, binding the caught error to `name` for the body.
+pub struct CatchClause {
+    pub name: Token,
+    pub body: Vec<Stmt>,
+}
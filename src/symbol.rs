@@ -0,0 +1,106 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// Don't bother purging the table until it holds this many symbols.
+const INITIAL_PURGE: usize = 1024;
+
+/// A string from the global symbol table. Every live `Symbol` with the same
+/// text shares one allocation, so comparing and hashing them only looks at
+/// the pointer, never the text.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+struct SymbolTable {
+    symbols: HashSet<Rc<str>>,
+    next_purge: usize,
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable { symbols: HashSet::new(), next_purge: INITIAL_PURGE });
+}
+
+impl Symbol {
+    pub fn intern(text: &str) -> Symbol {
+        SYMBOLS.with(|table| {
+            let mut table = table.borrow_mut();
+            if let Some(symbol) = table.symbols.get(text) {
+                return Symbol(Rc::clone(symbol));
+            }
+
+            // Strings built at runtime come and go, so drop the ones only the table still holds
+            if table.symbols.len() >= table.next_purge {
+                table.symbols.retain(|symbol| Rc::strong_count(symbol) > 1);
+                table.next_purge = INITIAL_PURGE.max(2 * table.symbols.len());
+            }
+
+            let symbol: Rc<str> = Rc::from(text);
+            table.symbols.insert(Rc::clone(&symbol));
+            Symbol(symbol)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(text: &str) -> Symbol {
+        Symbol::intern(text)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(text: String) -> Symbol {
+        Symbol::intern(&text)
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).cast::<u8>().hash(state);
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
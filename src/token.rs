@@ -1,34 +1,198 @@
 use crate::token_type::*;
+use crate::bigint::*;
+use crate::callable::*;
+use crate::class::*;
+use crate::vm::*;
+use crate::gc::Gc;
+use crate::symbol::*;
 use std::fmt;
 use std::ops::*;
+use std::rc::Rc;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Object {
+    Int(i64),
+    // An integer too large for an `i64`; never one that would fit
+    Big(Rc<BigInt>),
     Num(f64),
-    Str(String),
+    Str(Symbol),
     Nil,
     True,
     False,
+    Function(Gc<TikFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Gc<TikClass>),
+    Instance(Gc<TikInstance>),
+    Closure(Gc<Closure>),
     ArithmeticException,
+    DivisionByZero,
+    IntegerTooLarge,
+}
+
+/// The most bits an exact integer power may take, about 80,000 decimal digits.
+const MAX_POWER_BITS: u64 = 1 << 18;
+
+impl Object {
+    pub fn is_number(&self) -> bool {
+        matches!(self, Object::Int(_) | Object::Big(_) | Object::Num(_))
+    }
+
+    /// Text for a thrown value: strings without their quotes, and instances
+    /// by their `message` field when they have one.
+    pub fn describe(&self) -> String {
+        match self {
+            Object::Str(text) => text.to_string(),
+            Object::Instance(instance) => match instance.field(&Symbol::intern("message")) {
+                Some(message) => message.describe(),
+                None => self.to_string(),
+            },
+            value => value.to_string(),
+        }
+    }
+
+    /// Both operands as floats, when they're numbers. Mixed arithmetic
+    /// promotes the integer.
+    fn floats(&self, other: &Object) -> Option<(f64, f64)> {
+        Some((self.float()?, other.float()?))
+    }
+
+    fn float(&self) -> Option<f64> {
+        match self {
+            Object::Int(value) => Some(*value as f64),
+            Object::Big(value) => Some(value.to_f64()),
+            Object::Num(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn big(&self) -> Option<BigInt> {
+        match self {
+            Object::Int(value) => Some(BigInt::from(*value)),
+            Object::Big(value) => Some(BigInt::clone(value)),
+            _ => None,
+        }
+    }
+
+    /// An integer result, back in an `Int` whenever it fits in one.
+    pub fn integer(value: BigInt) -> Object {
+        match value.to_i64() {
+            Some(value) => Object::Int(value),
+            None => Object::Big(Rc::new(value)),
+        }
+    }
+
+    /// Applies an integer operation when both operands are integers: `small`
+    /// while it doesn't overflow, and `big` once either side needs a `BigInt`.
+    fn integers(&self, other: &Object, small: fn(i64, i64) -> Option<i64>, big: fn(&BigInt, &BigInt) -> BigInt) -> Option<Object> {
+        if let (Object::Int(left), Object::Int(right)) = (self, other) {
+            if let Some(result) = small(*left, *right) {
+                return Some(Object::Int(result));
+            }
+        }
+        Some(Object::integer(big(&self.big()?, &other.big()?)))
+    }
+
+    /// Integer arithmetic when both operands are integers, float arithmetic
+    /// when they're numbers of any other mix.
+    fn arithmetic(self, other: Object, small: fn(i64, i64) -> Option<i64>, big: fn(&BigInt, &BigInt) -> BigInt, float: fn(f64, f64) -> f64) -> Object {
+        if let Some(result) = self.integers(&other, small, big) {
+            return result;
+        }
+        match self.floats(&other) {
+            Some((left, right)) => Object::Num(float(left, right)),
+            None => Object::ArithmeticException,
+        }
+    }
+}
+
+impl Object {
+    /// `~/`: division rounded down to a whole number.
+    pub fn floor_div(self, rhs: Object) -> Object {
+        if self.divides_by_zero(&rhs) {
+            return Object::DivisionByZero;
+        }
+        self.arithmetic(rhs, floor_div, |left, right| left.div_mod_floor(right).0, |left, right| (left / right).floor())
+    }
+
+    /// `**`. An integer raised to a non-negative integer stays exact, up to
+    /// `MAX_POWER_BITS`; any other mix, including a negative exponent, is a float.
+    pub fn pow(self, rhs: Object) -> Object {
+        if let (Some(base), Object::Int(exponent)) = (self.big(), &rhs) {
+            if let Ok(exponent) = u32::try_from(*exponent) {
+                // A base of n bits raised to e has more than (n - 1) * e bits
+                if base.bits().saturating_sub(1) * u64::from(exponent) >= MAX_POWER_BITS {
+                    return Object::IntegerTooLarge;
+                }
+                if let Some(result) = base.to_i64().and_then(|base| base.checked_pow(exponent)) {
+                    return Object::Int(result);
+                }
+                return Object::integer(base.pow(exponent));
+            }
+        }
+        match self.floats(&rhs) {
+            Some((left, right)) => Object::Num(left.powf(right)),
+            None => Object::ArithmeticException,
+        }
+    }
+
+    /// Integer division by zero is an error; floats have infinities and NaN for it.
+    fn divides_by_zero(&self, divisor: &Object) -> bool {
+        self.big().is_some() && matches!(divisor, Object::Int(0))
+    }
+}
+
+/// Division that rounds the quotient down rather than towards zero, `None` on
+/// overflow like the other checked operations.
+fn floor_div(left: i64, right: i64) -> Option<i64> {
+    let quotient = left.checked_div(right)?;
+    if left % right != 0 && (left < 0) != (right < 0) {
+        Some(quotient - 1)
+    } else {
+        Some(quotient)
+    }
+}
+
+/// The remainder of `floor_div`, which takes the sign of the divisor.
+fn floor_mod(left: i64, right: i64) -> Option<i64> {
+    let remainder = left.checked_rem(right)?;
+    if remainder != 0 && (remainder < 0) != (right < 0) {
+        Some(remainder + right)
+    } else {
+        Some(remainder)
+    }
 }
 
 impl Sub for Object {
     type Output = Object;
     fn sub(self, rhs: Self) -> Self::Output {
-       match (self, rhs) {
-           (Object::Num(left), Object::Num(right)) => {Object::Num(left - right)},
-           _ => {Object::ArithmeticException},
-       }
+        self.arithmetic(rhs, i64::checked_sub, |left, right| left - right, |left, right| left - right)
     }
 }
 
+/// An integer when integers divide evenly, so `6 / 3` is `2`, and a float
+/// otherwise: `7 / 2` is `3.5`. Dividing an integer by `0` is an error.
 impl Div for Object {
     type Output = Object;
     fn div(self, rhs: Self) -> Self::Output {
+       if self.divides_by_zero(&rhs) {
+           return Object::DivisionByZero;
+       }
+       // Integers beyond 2^53 aren't exact as floats, so those are divided exactly first
+       const EXACT: u64 = 1 << f64::MANTISSA_DIGITS;
        match (self, rhs) {
-           (Object::Num(left), Object::Num(right)) => {Object::Num(left / right)},
-           _ => {Object::ArithmeticException},
+           (Object::Int(left), Object::Int(right)) if left.checked_rem(right) == Some(0) => Object::Int(left / right),
+           (Object::Int(left), Object::Int(right)) if left.unsigned_abs() <= EXACT && right.unsigned_abs() <= EXACT => Object::Num(left as f64 / right as f64),
+           (left, right) => match (left.big(), right.big()) {
+               (Some(left), Some(right)) => match left.div_mod_floor(&right) {
+                   (quotient, remainder) if remainder.is_zero() => Object::integer(quotient),
+                   _ => Object::Num(left.div_f64(&right)),
+               },
+               _ => match left.floats(&right) {
+                   Some((left, right)) => Object::Num(left / right),
+                   None => Object::ArithmeticException,
+               },
+           },
        }
     }
 }
@@ -36,10 +200,7 @@ impl Div for Object {
 impl Mul for Object {
     type Output = Object;
     fn mul(self, rhs: Self) -> Self::Output {
-       match (self, rhs) {
-           (Object::Num(left), Object::Num(right)) => {Object::Num(left * right)},
-           _ => {Object::ArithmeticException},
-       }
+        self.arithmetic(rhs, i64::checked_mul, |left, right| left * right, |left, right| left * right)
     }
 }
 
@@ -47,18 +208,46 @@ impl Add for Object {
     type Output = Object;
     fn add(self, rhs: Self) -> Self::Output {
        match (self, rhs) {
-           (Object::Num(left), Object::Num(right)) => {Object::Num(left + right)},
-           (Object::Str(left), Object::Str(right)) => {Object::Str(format!("{left}{right}"))},
-           _ => {Object::ArithmeticException},
+           (Object::Str(left), Object::Str(right)) => {Object::Str(Symbol::from(format!("{left}{right}")))},
+           (left, right) => left.arithmetic(right, i64::checked_add, |left, right| left + right, |left, right| left + right),
+       }
+    }
+}
+
+/// `%`, the remainder after `~/`, so `-7 % 3` is `2`.
+impl Rem for Object {
+    type Output = Object;
+    fn rem(self, rhs: Self) -> Self::Output {
+        if self.divides_by_zero(&rhs) {
+            return Object::DivisionByZero;
+        }
+        self.arithmetic(rhs, floor_mod, |left, right| left.div_mod_floor(right).1, |left, right| {
+            let remainder = left % right;
+            if remainder != 0.0 && (remainder < 0.0) != (right < 0.0) { remainder + right } else { remainder }
+        })
+    }
+}
+
+impl Neg for Object {
+    type Output = Object;
+    fn neg(self) -> Self::Output {
+       match self {
+           Object::Int(value) => value.checked_neg().map_or_else(|| Object::integer(-&BigInt::from(value)), Object::Int),
+           Object::Big(value) => Object::integer(-&*value),
+           Object::Num(value) => Object::Num(-value),
+           _ => Object::ArithmeticException,
        }
     }
 }
 
 impl PartialOrd for Object {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match(self, other) {
-            (Object::Num(left), Object::Num(right)) => left.partial_cmp(right),
-            _ => None,
+        match (self, other) {
+            (Object::Int(left), Object::Int(right)) => left.partial_cmp(right),
+            _ => match (self.big(), other.big()) {
+                (Some(left), Some(right)) => Some(left.cmp(&right)),
+                _ => self.floats(other).and_then(|(left, right)| left.partial_cmp(&right)),
+            },
         }
     }
 }
@@ -66,41 +255,59 @@ impl PartialOrd for Object {
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Object::Int(x) => write!(f, "{x}"),
+            Object::Big(x) => write!(f, "{x}"),
             Object::Num(x) => write!(f, "{x}"),
             Object::Str(x) => write!(f, "\"{x}\""),
             Object::Nil    => write!(f, "nil"),
             Object::True   => write!(f, "true"),
             Object::False  => write!(f, "false"),
-            Object::ArithmeticException => panic!("Unreachable for printing"),
+            Object::Function(x) => write!(f, "{x}"),
+            Object::Native(x) => write!(f, "{x}"),
+            Object::Class(x) => write!(f, "{x}"),
+            Object::Instance(x) => write!(f, "{x}"),
+            Object::Closure(x) => write!(f, "{x}"),
+            Object::ArithmeticException | Object::DivisionByZero | Object::IntegerTooLarge => panic!("Unreachable for printing"),
         }
     }
 }
 
+/// Where a token sits in the source: byte offsets into the source text and
+/// 1-based character columns, with `end` and `end_column` pointing just past it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub column: usize,
+    pub end_column: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub ttype: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Option<Object>,
     pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(ttype: TokenType, lexeme: String, literal: Option<Object>, line: usize) -> Token {
-        Token {ttype, lexeme, literal, line }
+    pub fn new(ttype: TokenType, lexeme: Symbol, literal: Option<Object>, line: usize, span: Span) -> Token {
+        Token {ttype, lexeme, literal, line, span }
     }
 
-    pub fn eof(line: usize) -> Token {
-        Token { ttype: TokenType::Eof, lexeme: "".to_string(), literal: None, line }
+    pub fn eof(line: usize, span: Span) -> Token {
+        Token { ttype: TokenType::Eof, lexeme: Symbol::intern(""), literal: None, line, span }
     }
 }
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?} {} {} {}", self.ttype, self.lexeme,
+        write!(f, "{:?} {} {} {}:{}", self.ttype, self.lexeme,
                if let Some(literal) = &self.literal {
                    literal.to_string()
                } else {
                    "None".to_string()
-               }, self.line)
+               }, self.line, self.span.column)
     }
 }
@@ -12,6 +12,9 @@ pub enum TokenType {
     SemiColon,
     Slash,
     Star,
+    StarStar,
+    Percent,
+    TildeSlash,
     Bang,
     BangEqual,
     Equal,
@@ -39,6 +42,10 @@ pub enum TokenType {
     Var,
     While,
     For,
+    Try,
+    Catch,
+    Finally,
+    Throw,
     Eof,
 }
 
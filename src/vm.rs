@@ -0,0 +1,584 @@
+use crate::callable::*;
+use crate::chunk::*;
+use crate::class::*;
+use crate::error::*;
+use crate::gc::{self, Gc, Trace, Tracer};
+use crate::operator::{self, is_truthy};
+use crate::token::*;
+use crate::symbol::*;
+use crate::token_type::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// How deep calls can nest before it's a stack overflow. The tree-walking
+/// interpreter uses the same limit, unless its native stack runs out sooner.
+pub const FRAMES_MAX: usize = 1024;
+
+/// A variable captured by a closure: a stack slot while the variable's scope
+/// is live, then its own copy once the scope ends.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Object),
+}
+
+pub struct Closure {
+    pub function: Rc<Function>,
+    upvalues: Vec<Gc<RefCell<Upvalue>>>,
+    // Set on methods bound to an instance; takes slot zero when called
+    receiver: Option<Object>,
+}
+
+impl Closure {
+    pub fn bind(&self, instance: Object) -> Closure {
+        Closure {
+            function: Rc::clone(&self.function),
+            upvalues: self.upvalues.clone(),
+            receiver: Some(instance),
+        }
+    }
+}
+
+impl Trace for RefCell<Upvalue> {
+    // An open upvalue's variable is on the stack, which is a root anyway
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Upvalue::Closed(value) = &*self.borrow() {
+            tracer.object(value);
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        for upvalue in &self.upvalues {
+            tracer.mark(*upvalue);
+        }
+        if let Some(receiver) = &self.receiver {
+            tracer.object(receiver);
+        }
+    }
+
+    fn extra_size(&self) -> usize {
+        gc::vec_size(&self.upvalues)
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.function.name)
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.function.name)
+    }
+}
+
+struct CallFrame {
+    closure: Gc<Closure>,
+    ip: usize,
+    // Stack index of slot zero
+    base: usize,
+    // The class being constructed when this frame runs its initializer
+    class: Option<Gc<TikClass>>,
+}
+
+impl CallFrame {
+    fn name(&self) -> String {
+        match &self.class {
+            Some(class) => class.name.clone(),
+            None => self.closure.function.name.clone(),
+        }
+    }
+
+    /// The line of the instruction the frame is executing, or last called from.
+    fn line(&self) -> usize {
+        let chunk = &self.closure.function.chunk;
+        chunk.line(self.ip.saturating_sub(1))
+    }
+}
+
+/// Where to resume when an error unwinds into a `try`.
+struct Handler {
+    frames: usize,
+    stack: usize,
+    target: usize,
+    pending: usize,
+}
+
+/// Runs compiled chunks on a value stack. Globals persist across calls to
+/// `interpret`, so the REPL keeps its definitions from one line to the next.
+pub struct Vm {
+    stack: Vec<Object>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Symbol, Object>,
+    open_upvalues: Vec<Gc<RefCell<Upvalue>>>,
+    handlers: Vec<Handler>,
+    // Errors caught by a handler, waiting for its `catch` or to be rethrown after its `finally`
+    pending: Vec<TikError>,
+    error_class: Gc<TikClass>,
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        let mut globals = HashMap::new();
+        for native in NativeFunction::globals() {
+            globals.insert(Symbol::from(native.name.clone()), Object::Native(Rc::new(native)));
+        }
+
+        Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals,
+            open_upvalues: Vec::new(),
+            handlers: Vec::new(),
+            pending: Vec::new(),
+            error_class: gc::alloc(TikClass::error()),
+        }
+    }
+
+    pub fn interpret(&mut self, function: Function) -> Result<(), TikError> {
+        let closure = gc::alloc(Closure { function: Rc::new(function), upvalues: Vec::new(), receiver: None });
+        self.stack.push(Object::Closure(closure));
+        self.frames.push(CallFrame { closure, ip: 0, base: 0, class: None });
+
+        loop {
+            let Err(e) = self.run() else {
+                return Ok(());
+            };
+
+            if let Some(handler) = self.handlers.pop() {
+                self.unwind(handler, e);
+            } else {
+                let e = self.trace(e, 0).with_script_frame();
+                self.reset();
+                return Err(e);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.handlers.clear();
+        self.pending.clear();
+    }
+
+    /// Drops everything above the handler's frame and jumps to it, keeping the
+    /// error for the handler's `catch` or `finally`.
+    fn unwind(&mut self, handler: Handler, e: TikError) {
+        let e = self.trace(e, handler.frames - 1);
+
+        self.frames.truncate(handler.frames);
+        self.close_upvalues(handler.stack);
+        self.stack.truncate(handler.stack);
+        self.frame().ip = handler.target;
+
+        self.pending.truncate(handler.pending);
+        self.pending.push(e);
+    }
+
+    /// Adds every frame above `down_to` to the error's stack trace, innermost first.
+    fn trace(&self, mut e: TikError, down_to: usize) -> TikError {
+        for index in (down_to + 1..self.frames.len()).rev() {
+            e = e.with_frame(self.frames[index].name(), self.frames[index - 1].line());
+        }
+        e
+    }
+
+    fn run(&mut self) -> Result<(), TikError> {
+        loop {
+            // Between instructions, everything in use is on the stack or reachable from the roots
+            if gc::due() {
+                self.collect_garbage();
+            }
+
+            let op = OpCode::from_byte(self.read_byte())
+                .ok_or_else(|| self.error(RuntimeError::UnsupportedOperator, "Unknown opcode".to_string()))?;
+
+            match op {
+                OpCode::Constant => {
+                    let index = self.read_u16();
+                    let value = self.chunk().constants[index as usize].clone();
+                    self.stack.push(value);
+                },
+                OpCode::Nil => self.stack.push(Object::Nil),
+                OpCode::True => self.stack.push(Object::True),
+                OpCode::False => self.stack.push(Object::False),
+                OpCode::Pop => {
+                    self.stack.pop();
+                },
+                OpCode::GetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                },
+                OpCode::SetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                },
+                OpCode::GetGlobal => {
+                    let index = self.read_u16();
+                    match self.globals.get(self.chunk().constant_name(index)) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(self.undefined(index)),
+                    }
+                },
+                OpCode::DefineGlobal => {
+                    let name = self.read_name();
+                    let value = self.stack.pop().unwrap();
+                    self.globals.insert(name, value);
+                },
+                OpCode::SetGlobal => {
+                    let index = self.read_u16();
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(self.frames.last().unwrap().closure.function.chunk.constant_name(index)) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.undefined(index)),
+                    }
+                },
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index];
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                },
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let value = self.peek(0).clone();
+                    let upvalue = self.frame().closure.upvalues[index];
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                },
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let Object::Instance(instance) = self.stack.pop().unwrap() else {
+                        return Err(self.error(RuntimeError::NotAnInstance, "Only instances have properties".to_string()));
+                    };
+                    match TikInstance::property(instance, &name) {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.error(RuntimeError::UndefinedProperty, format!("Undefined property '{name}'"))),
+                    }
+                },
+                OpCode::SetProperty => {
+                    let name = self.read_name();
+                    let value = self.stack.pop().unwrap();
+                    let Object::Instance(instance) = self.stack.pop().unwrap() else {
+                        return Err(self.error(RuntimeError::NotAnInstance, "Only instances have fields".to_string()));
+                    };
+                    instance.set_field(name, value.clone());
+                    self.stack.push(value);
+                },
+                OpCode::GetSuper => {
+                    let name = self.read_name();
+                    let superclass = self.stack.pop().unwrap();
+                    let this = self.stack.pop().unwrap();
+                    let method = match superclass {
+                        Object::Class(superclass) => superclass.find_method(&name),
+                        _ => None,
+                    };
+                    match method {
+                        Some(method) => self.stack.push(method.bind(this)),
+                        None => return Err(self.error(RuntimeError::UndefinedProperty, format!("Undefined property '{name}'"))),
+                    }
+                },
+                OpCode::Equal => self.binary(TokenType::EqualEqual)?,
+                OpCode::NotEqual => self.binary(TokenType::BangEqual)?,
+                OpCode::Greater => self.binary(TokenType::Greater)?,
+                OpCode::GreaterEqual => self.binary(TokenType::GreaterEqual)?,
+                OpCode::Less => self.binary(TokenType::Less)?,
+                OpCode::LessEqual => self.binary(TokenType::LessEqual)?,
+                OpCode::Add => self.binary(TokenType::Plus)?,
+                OpCode::Subtract => self.binary(TokenType::Minus)?,
+                OpCode::Multiply => self.binary(TokenType::Star)?,
+                OpCode::Divide => self.binary(TokenType::Slash)?,
+                OpCode::FloorDivide => self.binary(TokenType::TildeSlash)?,
+                OpCode::Modulo => self.binary(TokenType::Percent)?,
+                OpCode::Power => self.binary(TokenType::StarStar)?,
+                OpCode::Not => self.unary(TokenType::Bang)?,
+                OpCode::Negate => self.unary(TokenType::Minus)?,
+                OpCode::Print => {
+                    let value = self.stack.pop().unwrap();
+                    println!("{value}");
+                },
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame().ip += offset;
+                },
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if !is_truthy(self.peek(0)) {
+                        self.frame().ip += offset;
+                    }
+                },
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame().ip -= offset;
+                },
+                OpCode::Call => {
+                    let count = self.read_byte() as usize;
+                    self.call_value(self.peek(count).clone(), count)?;
+                },
+                OpCode::Closure => {
+                    let index = self.read_u16();
+                    let function = Rc::clone(&self.chunk().functions[index as usize]);
+
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        if is_local {
+                            let slot = self.frame().base + index;
+                            upvalues.push(self.capture_upvalue(slot));
+                        } else {
+                            upvalues.push(self.frame().closure.upvalues[index]);
+                        }
+                    }
+
+                    self.stack.push(Object::Closure(gc::alloc(Closure { function, upvalues, receiver: None })));
+                },
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.stack.pop();
+                },
+                OpCode::Return => {
+                    let result = self.stack.pop().unwrap();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                },
+                OpCode::Inherit => {
+                    if !matches!(self.peek(0), Object::Class(_)) {
+                        return Err(self.error(RuntimeError::SuperclassNotClass, "Superclass must be a class".to_string()));
+                    }
+                },
+                OpCode::Class => {
+                    let name = self.read_name();
+                    let count = self.read_u16() as usize;
+                    let has_superclass = self.read_byte() == 1;
+
+                    let mut methods = HashMap::new();
+                    for method in self.stack.split_off(self.stack.len() - count) {
+                        if let Object::Closure(closure) = method {
+                            methods.insert(Symbol::from(closure.function.name.clone()), Method::Closure(closure));
+                        }
+                    }
+
+                    let superclass = match (has_superclass, self.peek(0)) {
+                        (true, Object::Class(superclass)) => Some(*superclass),
+                        _ => None,
+                    };
+                    self.stack.push(Object::Class(gc::alloc(TikClass::new(name.to_string(), superclass, methods))));
+                },
+                OpCode::PushHandler => {
+                    let offset = self.read_u16() as usize;
+                    let handler = Handler {
+                        frames: self.frames.len(),
+                        stack: self.stack.len(),
+                        target: self.frame().ip + offset,
+                        pending: self.pending.len(),
+                    };
+                    self.handlers.push(handler);
+                },
+                OpCode::PopHandler => {
+                    self.handlers.pop();
+                },
+                OpCode::Catch => {
+                    let e = self.pending.pop().unwrap();
+                    self.stack.push(TikInstance::from_error(self.error_class, e));
+                },
+                OpCode::Rethrow => return Err(self.pending.pop().unwrap()),
+                OpCode::Throw => {
+                    let value = self.stack.pop().unwrap();
+                    let (line, span) = self.location();
+                    let message = value.describe();
+                    return Err(TikError::thrown(line, span, value, message));
+                },
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Object, count: usize) -> Result<(), TikError> {
+        match callee {
+            Object::Closure(closure) => self.call(closure, count, None),
+            Object::Native(native) => {
+                if count != native.arity {
+                    return Err(self.arity_error(native.arity, count));
+                }
+                let arguments = self.stack.split_off(self.stack.len() - count);
+                self.stack.pop();
+                self.stack.push((native.function)(&arguments));
+                Ok(())
+            },
+            Object::Class(class) => {
+                let base = self.stack.len() - count - 1;
+                let instance = Object::Instance(gc::alloc(TikInstance::new(class)));
+                self.stack[base] = instance.clone();
+
+                match class.find_method(&Symbol::intern("init")) {
+                    Some(Method::Closure(initializer)) => self.call(gc::alloc(initializer.bind(instance)), count, Some(class)),
+                    _ if count != 0 => Err(self.arity_error(0, count)),
+                    _ => Ok(()),
+                }
+            },
+            _ => Err(self.error(RuntimeError::NotCallable, "Can only call functions and classes".to_string())),
+        }
+    }
+
+    fn call(&mut self, closure: Gc<Closure>, count: usize, class: Option<Gc<TikClass>>) -> Result<(), TikError> {
+        if count != closure.function.arity {
+            return Err(self.arity_error(closure.function.arity, count));
+        }
+
+        // The script's own frame doesn't count towards the limit
+        if self.frames.len() > FRAMES_MAX {
+            return Err(self.error(RuntimeError::StackOverflow, "Stack overflow".to_string()));
+        }
+
+        let base = self.stack.len() - count - 1;
+        if let Some(receiver) = &closure.receiver {
+            self.stack[base] = receiver.clone();
+        }
+        self.frames.push(CallFrame { closure, ip: 0, base, class });
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Gc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot) {
+                return *upvalue;
+            }
+        }
+
+        let upvalue = gc::alloc(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    /// Moves every captured variable at or above `from` off the stack.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= from => slot,
+                _ => return true,
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+
+    /// Collects every heap object the VM can no longer reach.
+    pub fn collect_garbage(&self) {
+        gc::collect(|tracer| {
+            for value in self.stack.iter().chain(self.globals.values()) {
+                tracer.object(value);
+            }
+            for frame in &self.frames {
+                tracer.mark(frame.closure);
+                if let Some(class) = frame.class {
+                    tracer.mark(class);
+                }
+            }
+            for upvalue in &self.open_upvalues {
+                tracer.mark(*upvalue);
+            }
+            for value in self.pending.iter().filter_map(TikError::value) {
+                tracer.object(value);
+            }
+            tracer.mark(self.error_class);
+        });
+    }
+
+    fn binary(&mut self, operator: TokenType) -> Result<(), TikError> {
+        let right = self.stack.pop().unwrap();
+        let left = self.stack.pop().unwrap();
+        let (line, span) = self.location();
+        self.stack.push(operator::binary(operator, left, right).map_err(|e| e.at(line, span))?);
+        Ok(())
+    }
+
+    fn unary(&mut self, operator: TokenType) -> Result<(), TikError> {
+        let right = self.stack.pop().unwrap();
+        let (line, span) = self.location();
+        self.stack.push(operator::unary(operator, right).map_err(|e| e.at(line, span))?);
+        Ok(())
+    }
+
+    fn frame(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.frames.last().unwrap().closure.function.chunk
+    }
+
+    fn peek(&self, distance: usize) -> &Object {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frames.last_mut().unwrap();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frames.last_mut().unwrap();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_name(&mut self) -> Symbol {
+        let index = self.read_u16();
+        self.chunk().constant_name(index).clone()
+    }
+
+    /// The source location of the instruction being executed.
+    fn location(&self) -> (usize, Span) {
+        let frame = self.frames.last().unwrap();
+        let chunk = &frame.closure.function.chunk;
+        chunk.location(frame.ip.saturating_sub(1))
+    }
+
+    fn error(&self, kind: RuntimeError, message: String) -> TikError {
+        let (line, span) = self.location();
+        TikError::runtime_error_at(kind, line, span, message)
+    }
+
+    fn undefined(&self, name: u16) -> TikError {
+        let (line, span) = self.location();
+        TikError::undefined_variable(self.chunk().constant_name(name), line, span)
+    }
+
+    fn arity_error(&self, arity: usize, count: usize) -> TikError {
+        self.error(RuntimeError::ArityMismatch, format!("Expected {arity} arguments but got {count}"))
+    }
+}
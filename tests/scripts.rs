@@ -0,0 +1,610 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_ERROR: &str = "// expect error: ";
+
+fn tik(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tik"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn tik");
+
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Both execution backends run every script and must agree on the output.
+const BACKENDS: [&str; 2] = ["--backend=tree", "--backend=vm"];
+
+/// Runs a script under `tests/scripts` on each backend, with and without
+/// `--gc-stress`, and checks its output against the `// expect: ` and
+/// `// expect error: ` comments it contains, which must account for every error
+/// reported. An expected error may start with a `line:column` location, e.g.
+/// `// expect error: 3:5 Expect ';'`.
+fn check(name: &str) {
+    check_with(name, &[]);
+}
+
+/// `check`, passing `flags` to every run.
+fn check_with(name: &str, flags: &[&str]) {
+    let path = format!("{}/tests/scripts/{name}", env!("CARGO_MANIFEST_DIR"));
+    let source = fs::read_to_string(&path).unwrap();
+
+    let mut expected_out = Vec::new();
+    let mut expected_err = Vec::new();
+    for line in source.lines() {
+        if let Some(index) = line.find(EXPECT) {
+            expected_out.push(line[index + EXPECT.len()..].to_string());
+        } else if let Some(index) = line.find(EXPECT_ERROR) {
+            expected_err.push(line[index + EXPECT_ERROR.len()..].to_string());
+        }
+    }
+
+    // Collecting on every allocation must not change what any script does
+    for (backend, stress) in BACKENDS.into_iter().flat_map(|backend| [(backend, false), (backend, true)]) {
+        let mut args = vec![backend];
+        if stress {
+            args.push("--gc-stress");
+        }
+        args.extend(flags);
+        args.push(&path);
+
+        let output = tik(&args, "");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let backend = args[..args.len() - 1].join(" ");
+
+        assert_eq!(stdout.lines().collect::<Vec<_>>(), expected_out, "stdout of {name} with {backend}");
+        for error in &expected_err {
+            let (location, message) = match error.split_once(' ') {
+                Some((location, message)) if location.contains(':') && location.starts_with(|c: char| c.is_ascii_digit()) => {
+                    (Some(location), message)
+                },
+                _ => (None, error.as_str()),
+            };
+
+            assert!(stderr.contains(&format!("]: {message}")), "{name} with {backend}: expected '{message}' in stderr:\n{stderr}");
+            if let Some(location) = location {
+                assert!(stderr.contains(&format!("{path}:{location}\n")), "{name} with {backend}: expected '{location}' in stderr:\n{stderr}");
+            }
+        }
+        let reported = stderr.lines().filter(|line| line.starts_with("error[")).count();
+        assert_eq!(reported, expected_err.len(), "{name} with {backend}: unexpected errors in stderr:\n{stderr}");
+        assert_eq!(output.status.success(), expected_err.is_empty(), "exit status of {name} with {backend}");
+    }
+}
+
+#[test]
+fn block_nested() {
+    check("block/nested.tik");
+}
+
+#[test]
+fn block_shadowing() {
+    check("block/shadowing.tik");
+}
+
+#[test]
+fn block_error_restores_scope() {
+    for backend in BACKENDS {
+        let output = tik(&[backend], "var a = \"outer\";\n{ var a = \"inner\"; print b; }\nprint a;\n");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Undefined variable 'b'"));
+        assert!(stdout.contains("\"outer\""), "{backend} stdout: {stdout}");
+        assert!(!stdout.contains("\"inner\""), "{backend} stdout: {stdout}");
+    }
+}
+
+#[test]
+fn variable_assign_undeclared() {
+    check("variable/assign_undeclared.tik");
+}
+
+#[test]
+fn control_flow_if() {
+    check("control_flow/if.tik");
+}
+
+#[test]
+fn control_flow_loops() {
+    check("control_flow/loops.tik");
+}
+
+#[test]
+fn logical_and_or() {
+    check("logical/and_or.tik");
+}
+
+#[test]
+fn function_closures() {
+    check("function/closures.tik");
+}
+
+#[test]
+fn function_arity() {
+    check("function/arity.tik");
+}
+
+#[test]
+fn function_not_callable() {
+    check("function/not_callable.tik");
+}
+
+#[test]
+fn function_upvalues() {
+    check("function/upvalues.tik");
+}
+
+#[test]
+fn function_stack_overflow() {
+    check("function/stack_overflow.tik");
+}
+
+#[test]
+fn class_methods() {
+    check("class/methods.tik");
+}
+
+#[test]
+fn class_init_arity() {
+    check("class/init_arity.tik");
+}
+
+#[test]
+fn inheritance_super() {
+    check("inheritance/super.tik");
+}
+
+#[test]
+fn inheritance_inherit_self() {
+    check("inheritance/inherit_self.tik");
+}
+
+#[test]
+fn inheritance_inherit_non_class() {
+    check("inheritance/inherit_non_class.tik");
+}
+
+#[test]
+fn resolver_closure_binding() {
+    check("resolver/closure_binding.tik");
+}
+
+#[test]
+fn resolver_errors() {
+    check("resolver/errors.tik");
+}
+
+#[test]
+fn exception_catch() {
+    check("exception/catch.tik");
+}
+
+#[test]
+fn exception_finally() {
+    check("exception/finally.tik");
+}
+
+#[test]
+fn exception_return() {
+    check("exception/return.tik");
+}
+
+#[test]
+fn exception_uncaught() {
+    check("exception/uncaught.tik");
+}
+
+#[test]
+fn exception_syntax() {
+    check("exception/syntax.tik");
+}
+
+/// Too long-running to repeat under `--gc-stress`, so not a `check` script.
+#[test]
+fn gc_cycles() {
+    let path = format!("{}/tests/scripts/gc/cycles.tik", env!("CARGO_MANIFEST_DIR"));
+
+    for backend in BACKENDS {
+        let output = tik(&[backend, "--gc-stats", &path], "");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "40000\n", "{backend}");
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stats: Vec<usize> = stderr.trim().strip_prefix("gc: ").unwrap_or_else(|| panic!("{backend}: {stderr}"))
+            .split(", ")
+            .map(|stat| stat.split(' ').next().unwrap().parse().unwrap())
+            .collect();
+        let [allocated, freed, live, collections] = stats[..] else { panic!("{backend}: {stderr}") };
+
+        // Every iteration's cycles are garbage by the end, so only the globals are left
+        assert!(allocated > 4 * 1024 * 1024, "{backend}: {stderr}");
+        assert_eq!(allocated - freed, live);
+        assert!(live < 16 * 1024, "{backend}: {stderr}");
+        assert!(collections > 1, "{backend}: {stderr}");
+    }
+}
+
+#[test]
+fn number_integers() {
+    check("number/integers.tik");
+}
+
+#[test]
+fn number_big() {
+    check("number/big.tik");
+    check_with("number/big.tik", &["-O"]);
+}
+
+#[test]
+fn number_operators() {
+    check("number/operators.tik");
+    check_with("number/operators.tik", &["-O"]);
+}
+
+#[test]
+fn optimizer_fold() {
+    check("optimizer/fold.tik");
+    check_with("optimizer/fold.tik", &["-O"]);
+}
+
+#[test]
+fn optimizer_emit() {
+    let path = format!("{}/tests/scripts/optimizer/fold.tik", env!("CARGO_MANIFEST_DIR"));
+
+    let output = tik(&["--emit=optimized-ast", &path], "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "\
+(print 7)
+(print 4)
+(print \"concat\")
+(print false)
+(var x = 10)
+(print (* x 5))
+(print 1024)
+(if false (print (** 2 4000000000)))
+(print (+ 3 true))
+");
+    assert!(output.status.success());
+
+    let output = tik(&["-O", "--emit=ast", &path], "");
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("(print (+ 1 (* 2 3)))\n"));
+}
+
+/// Folding must not change what any script prints or how it fails.
+#[test]
+fn optimizer_preserves_behaviour() {
+    fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                scripts(&path, found);
+            } else if path.extension().is_some_and(|extension| extension == "tik") {
+                found.push(path);
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    scripts(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts"), &mut found);
+
+    for path in found {
+        let path = path.to_string_lossy();
+        for backend in BACKENDS {
+            let expected = tik(&[backend, &path], "");
+            let output = tik(&[backend, "-O", &path], "");
+            assert_eq!(output.stdout, expected.stdout, "{path} with {backend}");
+            assert_eq!(String::from_utf8_lossy(&output.stderr), String::from_utf8_lossy(&expected.stderr), "{path} with {backend}");
+            assert_eq!(output.status.code(), expected.status.code(), "{path} with {backend}");
+        }
+    }
+}
+
+#[test]
+fn string_interning() {
+    check("string/interning.tik");
+}
+
+#[test]
+fn equality_values() {
+    check("equality/values.tik");
+}
+
+/// `==` and `!=` between every pair of kinds of value. Values in the same
+/// group are equal to each other, except NaN, which isn't even equal to itself.
+#[test]
+fn equality_matrix() {
+    let values = [
+        ("1", 0),
+        ("2", 1),
+        ("0.0 / 0", 2),
+        ("\"ab\"", 3),
+        ("\"a\" + \"b\"", 3),
+        ("\"b\"", 4),
+        ("nil", 5),
+        ("true", 6),
+        ("false", 7),
+        ("f", 8),
+        ("g", 9),
+        ("clock", 10),
+        ("A", 11),
+        ("B", 12),
+        ("a", 13),
+        ("b", 14),
+        ("method", 15),
+    ];
+    let nan = 2;
+
+    let mut source = String::from("\
+fun f() {}
+fun g() {}
+class A { m() {} }
+class B {}
+var a = A();
+var b = B();
+var method = a.m;
+");
+    let mut expected = String::new();
+    for (left, left_group) in values {
+        for (right, right_group) in values {
+            let equal = left_group == right_group && left_group != nan;
+            source += &format!("print {left} == {right};\nprint {left} != {right};\n");
+            expected += &format!("{equal}\n{}\n", !equal);
+        }
+    }
+
+    let path = format!("{}/equality_matrix.tik", env!("CARGO_TARGET_TMPDIR"));
+    fs::write(&path, source).unwrap();
+    for backend in BACKENDS {
+        let output = tik(&[backend, &path], "");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected, "{backend}: {}", String::from_utf8_lossy(&output.stderr));
+        assert!(output.status.success(), "{backend}");
+    }
+}
+
+#[test]
+fn syntax_recovery() {
+    check("syntax/recovery.tik");
+}
+
+#[test]
+fn syntax_unexpected_character() {
+    check("syntax/unexpected_character.tik");
+}
+
+#[test]
+fn syntax_too_deeply_nested() {
+    check("syntax/too_deeply_nested.tik");
+}
+
+#[test]
+fn diagnostics_render() {
+    let path = format!("{}/tests/scripts/diagnostics/render.tik", env!("CARGO_MANIFEST_DIR"));
+    let output = tik(&[&path], "");
+
+    let expected = format!("\
+error[E0401]: Undefined variable 'missing'
+ --> {path}:2:18
+  |
+2 | print greeting + missing;
+  |                  ^^^^^^^
+  = help: declare it first with `var missing = ...;`
+");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), expected);
+}
+
+#[test]
+fn diagnostics_json() {
+    let path = format!("{}/tests/scripts/diagnostics/json.tik", env!("CARGO_MANIFEST_DIR"));
+    let output = tik(&["--error-format=json", &path], "");
+
+    let expected = format!("{{\"severity\":\"error\",\"code\":\"E0401\",\"message\":\"Undefined variable 'missing'\",\
+        \"file\":\"{path}\",\"line\":1,\"column\":20,\"span\":{{\"start\":19,\"end\":26,\"end_column\":27}},\
+        \"help\":\"declare it first with `var missing = ...;`\",\"trace\":[]}}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), expected);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn diagnostics_stack_trace() {
+    let path = format!("{}/tests/scripts/diagnostics/stack_trace.tik", env!("CARGO_MANIFEST_DIR"));
+    let expected = format!("\
+stack backtrace:
+   0: inner at {path}:2
+   1: middle at {path}:6
+   2: Runner at {path}:12
+   3: <script> at {path}:16
+");
+
+    for backend in BACKENDS {
+        let output = tik(&[backend, &path], "");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.ends_with(&expected), "{backend}: {stderr}");
+        assert_eq!(output.status.code(), Some(70));
+
+        let output = tik(&[backend, "--error-format=json", &path], "");
+        let file = format!("\"file\":\"{path}\"");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(&format!("\"trace\":[{{\"function\":\"inner\",{file},\"line\":2}},\
+            {{\"function\":\"middle\",{file},\"line\":6}},{{\"function\":\"Runner\",{file},\"line\":12}},\
+            {{\"function\":\"<script>\",{file},\"line\":16}}]")), "{backend}: {stderr}");
+    }
+}
+
+#[test]
+fn diagnostics_missing_file() {
+    let output = tik(&["--error-format=json", "missing.tik"], "");
+
+    assert_eq!(String::from_utf8_lossy(&output.stderr).lines().count(), 1);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\"code\":\"E0501\""));
+    assert_eq!(output.status.code(), Some(66));
+}
+
+/// Compiles a script under `tests/scripts` to a `.tikc` file in the test
+/// scratch directory and returns the paths of both.
+fn compile(name: &str) -> (String, String) {
+    let path = format!("{}/tests/scripts/{name}", env!("CARGO_MANIFEST_DIR"));
+    let output = format!("{}/{}c", env!("CARGO_TARGET_TMPDIR"), name.replace('/', "_"));
+
+    let result = tik(&["compile", &path, &output], "");
+    assert!(result.status.success(), "compiling {name}: {}", String::from_utf8_lossy(&result.stderr));
+    (path, output)
+}
+
+#[test]
+fn bytecode_round_trip() {
+    for name in ["function/upvalues.tik", "inheritance/super.tik", "exception/finally.tik", "number/big.tik"] {
+        let (path, compiled) = compile(name);
+        let expected = tik(&["--backend=vm", &path], "");
+
+        for args in [&["run", compiled.as_str()][..], &[compiled.as_str()]] {
+            let output = tik(args, "");
+            assert_eq!(String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&expected.stdout), "{name}");
+            assert!(output.status.success(), "{name}: {}", String::from_utf8_lossy(&output.stderr));
+        }
+    }
+}
+
+#[test]
+fn bytecode_runtime_error() {
+    let (path, compiled) = compile("diagnostics/stack_trace.tik");
+    let output = tik(&["run", &compiled], "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(stderr.contains(&format!("   0: inner at {path}:2\n")), "{stderr}");
+    assert_eq!(output.status.code(), Some(70));
+}
+
+#[test]
+fn bytecode_disassemble() {
+    let (path, compiled) = compile("function/closures.tik");
+
+    for file in [path, compiled] {
+        let output = tik(&["disassemble", &file], "");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        assert!(stdout.starts_with("== <script> ==\n0000    1 "), "{stdout}");
+        assert!(stdout.contains("DefineGlobal"), "{stdout}");
+        assert!(stdout.contains("\"makeCounter\""), "{stdout}");
+        assert!(stdout.contains("== makeCounter ==\n"), "{stdout}");
+        assert!(stdout.lines().any(|line| line.ends_with("Return")), "{stdout}");
+    }
+}
+
+#[test]
+fn bytecode_truncated() {
+    let (_, compiled) = compile("logical/and_or.tik");
+    let bytes = fs::read(&compiled).unwrap();
+
+    for length in [0, 5, bytes.len() / 2, bytes.len() - 1] {
+        let truncated = compiled.replace(".tikc", &format!("_{length}.tikc"));
+        fs::write(&truncated, &bytes[..length]).unwrap();
+
+        let output = tik(&["--error-format=json", "run", &truncated], "");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let code = if length < 4 { "E0701" } else { "E0703" };
+        assert!(stderr.contains(&format!("\"code\":\"{code}\"")), "{length}: {stderr}");
+        assert_eq!(output.status.code(), Some(65));
+    }
+}
+
+#[test]
+fn bytecode_version_mismatch() {
+    let (_, compiled) = compile("block/nested.tik");
+    let mut bytes = fs::read(&compiled).unwrap();
+    bytes[4] = bytes[4].wrapping_add(1);
+    let mismatched = compiled.replace(".tikc", "_version.tikc");
+    fs::write(&mismatched, bytes).unwrap();
+
+    let output = tik(&["run", &mismatched], "");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error[E0702]: "));
+    assert_eq!(output.status.code(), Some(65));
+}
+
+/// Rewrites the top-level code of a compiled file and recomputes its checksum,
+/// so the file loads and only the code itself is wrong.
+fn patch_code(bytes: &mut [u8], patch: impl FnOnce(&mut [u8])) {
+    let u32_at = |bytes: &[u8], at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+
+    // Past the header, the source path and the script's name, arity and upvalue count
+    let mut at = 10;
+    at += 4 + u32_at(bytes, at);
+    at += 4 + u32_at(bytes, at);
+    at += 8;
+    let length = u32_at(bytes, at);
+    patch(&mut bytes[at + 4..at + 4 + length]);
+
+    let checksum = bytes[10..].iter().fold(0x811c9dc5u32, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+    bytes[6..10].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// An error raised by code with no source position, like an empty script's
+/// implicit return, is reported without a column.
+#[test]
+fn bytecode_no_position() {
+    let path = format!("{}/no_position.tik", env!("CARGO_TARGET_TMPDIR"));
+    let compiled = format!("{}/no_position.tikc", env!("CARGO_TARGET_TMPDIR"));
+    fs::write(&path, "print 1;").unwrap();
+    assert!(tik(&["compile", &path, &compiled], "").status.success());
+
+    let mut bytes = fs::read(&compiled).unwrap();
+    // Print becomes Nil, then the implicit return's Nil becomes Inherit, which
+    // rejects that nil as a superclass
+    patch_code(&mut bytes, |code| code[3..5].copy_from_slice(&[1, 38]));
+    fs::write(&compiled, bytes).unwrap();
+
+    let output = tik(&[&compiled], "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("error[E0407]: "), "{stderr}");
+    assert!(stderr.contains(&format!("--> {path}:1\n")), "{stderr}");
+    assert_eq!(output.status.code(), Some(70));
+
+    let output = tik(&["--error-format=json", &compiled], "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("\"line\":1,\"column\":null,\"span\":null"), "{stderr}");
+}
+
+/// Code that loads with a valid checksum but would misuse the stack is refused
+/// before any of it runs.
+#[test]
+fn bytecode_malformed_code() {
+    let path = format!("{}/print_one.tik", env!("CARGO_TARGET_TMPDIR"));
+    let compiled = format!("{}/print_one.tikc", env!("CARGO_TARGET_TMPDIR"));
+    fs::write(&path, "print 1;").unwrap();
+    assert!(tik(&["compile", &path, &compiled], "").status.success());
+    let bytes = fs::read(&compiled).unwrap();
+
+    // Print becomes Greater, which would pop the script's own slot, then Catch
+    // and PopHandler, which have no error or handler to pop
+    for op in [17, 42, 41] {
+        let mut patched = bytes.clone();
+        patch_code(&mut patched, |code| code[3] = op);
+        let malformed = compiled.replace(".tikc", &format!("_{op}.tikc"));
+        fs::write(&malformed, patched).unwrap();
+
+        let output = tik(&["run", &malformed], "");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with("error[E0704]: "), "{op}: {stderr}");
+        assert!(output.stdout.is_empty(), "{op}");
+        assert_eq!(output.status.code(), Some(65));
+    }
+}
+
+#[test]
+fn bytecode_corrupted() {
+    let (_, compiled) = compile("class/methods.tik");
+    let mut bytes = fs::read(&compiled).unwrap();
+    // The first character of the stored source path
+    bytes[14] ^= 0x20;
+    let corrupted = compiled.replace(".tikc", "_corrupted.tikc");
+    fs::write(&corrupted, bytes).unwrap();
+
+    let output = tik(&["run", &corrupted], "");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error[E0704]: "));
+    assert_eq!(output.status.code(), Some(65));
+}